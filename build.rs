@@ -1,16 +1,88 @@
-fn main() {
-    if cfg!(target_os = "windows") {
-        let opencv_libs = if cfg!(debug_assertions) {
-            vec!["opencv_world480d"]
-        } else {
-            vec!["opencv_world480"]
-        };
-
-        for lib in opencv_libs {
-            println!("cargo:rustc-link-lib=dylib={}", lib);
-        }
-
-        println!("cargo:rustc-link-search=native=C:/tools/opencv/build/x64/vc16/lib");
-        println!("cargo:rustc-link-search=native=C:/tools/opencv/build/x64/vc16/bin");
-    }
-}
\ No newline at end of file
+use std::env;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_OPENCV_ROOT: &str = "C:/tools/opencv/build/x64";
+// Modules the pipeline uses, for when pkg-config cannot list them
+const UNIX_OPENCV_LIBS: [&str; 6] = [
+    "opencv_core",
+    "opencv_imgproc",
+    "opencv_imgcodecs",
+    "opencv_dnn",
+    "opencv_videoio",
+    "opencv_ximgproc",
+];
+
+fn main() {
+    // Label-only builds do not link OpenCV at all
+    if env::var_os("CARGO_FEATURE_INFERENCE").is_none() {
+        return;
+    }
+    // cfg! in a build script describes the host; cross builds need the target's OS
+    match env::var("CARGO_CFG_TARGET_OS").as_deref() {
+        Ok("windows") => link_windows(),
+        Ok("macos") => link_unix(true),
+        Ok("linux") => link_unix(false),
+        _ => {}
+    }
+}
+
+// pkg-config prints the rustc-link-lib and rustc-link-search lines itself when it succeeds
+fn link_unix(macos: bool) {
+    let error = match pkg_config::Config::new().probe("opencv4") {
+        Ok(_) => return,
+        Err(e) => e,
+    };
+    if !macos {
+        panic!(
+            "pkg-config could not find opencv4; install the OpenCV development package (e.g. libopencv-dev), \
+             or build with --no-default-features for the label tools only\n\n{}",
+            error
+        );
+    }
+    // Homebrew's default prefix on Intel Macs; the error text spans several lines, but a
+    // cargo:warning must fit on one
+    println!(
+        "cargo:warning=pkg-config could not find opencv4 ({}), falling back to /usr/local/lib",
+        error.to_string().replace('\n', " ")
+    );
+    println!("cargo:rustc-link-search=native=/usr/local/lib");
+    for lib in UNIX_OPENCV_LIBS {
+        println!("cargo:rustc-link-lib=dylib={}", lib);
+    }
+}
+
+fn link_windows() {
+    println!("cargo:rerun-if-env-changed=OPENCV_DIR");
+    println!("cargo:rerun-if-env-changed=OPENCV_LINK_LIBS");
+
+    let opencv_libs: Vec<String> = match env::var("OPENCV_LINK_LIBS") {
+        // Same comma-separated format the opencv crate reads
+        Ok(libs) => libs
+            .split(',')
+            .map(|lib| lib.trim().to_string())
+            .filter(|lib| !lib.is_empty())
+            .collect(),
+        // PROFILE is the profile being built; cfg!(debug_assertions) would describe this script
+        Err(_) if env::var("PROFILE").as_deref() == Ok("debug") => vec!["opencv_world480d".to_string()],
+        Err(_) => vec!["opencv_world480".to_string()],
+    };
+    for lib in opencv_libs {
+        println!("cargo:rustc-link-lib=dylib={}", lib);
+    }
+
+    let opencv_dir = match env::var("OPENCV_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => {
+            // The prebuilt Windows packages use vc16 for Visual Studio 2019 and vc17 for 2022
+            let root = Path::new(DEFAULT_OPENCV_ROOT);
+            let dir = if root.join("vc17").exists() { root.join("vc17") } else { root.join("vc16") };
+            println!(
+                "cargo:warning=OPENCV_DIR is not set, falling back to {}; set OPENCV_DIR (e.g. C:/tools/opencv/build/x64/vc17) and OPENCV_LINK_LIBS to match your install",
+                dir.display()
+            );
+            dir
+        }
+    };
+    println!("cargo:rustc-link-search=native={}", opencv_dir.join("lib").display());
+    println!("cargo:rustc-link-search=native={}", opencv_dir.join("bin").display());
+}
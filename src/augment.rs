@@ -0,0 +1,345 @@
+use std::sync::{Arc, Mutex};
+use image::{DynamicImage, GenericImageView};
+use opencv::{core, imgproc, prelude::*};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::{image_to_mat, mat_to_image, Detection, ProcessingError, Task};
+
+#[derive(Debug, Clone, Copy)]
+enum Augmentation {
+    HorizontalFlip { prob: f32 },
+    Rotation { max_degrees: f32, prob: f32 },
+    RandomCrop { scale_range: (f32, f32), prob: f32 },
+    ColorJitter { brightness: f32, contrast: f32, saturation: f32, prob: f32 },
+}
+
+#[derive(Clone)]
+pub struct AugmentationPipeline {
+    augmentations: Vec<Augmentation>,
+    variants: usize,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+#[allow(dead_code)]
+impl AugmentationPipeline {
+    pub fn new(variants: usize, seed: u64) -> Self {
+        Self {
+            augmentations: Vec::new(),
+            variants: variants.max(1),
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    pub fn with_horizontal_flip(mut self, prob: f32) -> Self {
+        self.augmentations.push(Augmentation::HorizontalFlip { prob });
+        self
+    }
+
+    pub fn with_rotation(mut self, max_degrees: f32, prob: f32) -> Self {
+        self.augmentations.push(Augmentation::Rotation { max_degrees, prob });
+        self
+    }
+
+    pub fn with_random_crop(mut self, scale_range: (f32, f32), prob: f32) -> Self {
+        self.augmentations.push(Augmentation::RandomCrop { scale_range, prob });
+        self
+    }
+
+    // Photometric only, so labels pass through unchanged
+    pub fn with_color_jitter(mut self, brightness: f32, contrast: f32, saturation: f32, prob: f32) -> Self {
+        self.augmentations.push(Augmentation::ColorJitter {
+            brightness,
+            contrast,
+            saturation,
+            prob,
+        });
+        self
+    }
+
+    fn augment(
+        &self,
+        rng: &mut StdRng,
+        image: &DynamicImage,
+        labels: &[Detection],
+    ) -> Result<(DynamicImage, Vec<Detection>), ProcessingError> {
+        let mut image = image.clone();
+        let mut labels = labels.to_vec();
+
+        for augmentation in &self.augmentations {
+            match *augmentation {
+                Augmentation::HorizontalFlip { prob } => {
+                    if rng.gen::<f32>() < prob {
+                        image = image.fliph();
+                        for label in &mut labels {
+                            label.x_center = 1.0 - label.x_center;
+                        }
+                    }
+                }
+                Augmentation::Rotation { max_degrees, prob } => {
+                    if rng.gen::<f32>() < prob {
+                        let angle = rng.gen_range(-max_degrees..=max_degrees);
+                        (image, labels) = rotate(&image, &labels, angle)?;
+                    }
+                }
+                Augmentation::RandomCrop { scale_range, prob } => {
+                    if rng.gen::<f32>() < prob {
+                        let scale = rng.gen_range(scale_range.0.min(scale_range.1)..=scale_range.0.max(scale_range.1));
+                        let (width, height) = image.dimensions();
+                        let crop_width = ((width as f32 * scale) as u32).clamp(1, width);
+                        let crop_height = ((height as f32 * scale) as u32).clamp(1, height);
+                        let x = rng.gen_range(0..=width - crop_width);
+                        let y = rng.gen_range(0..=height - crop_height);
+                        labels = crop_labels(&labels, (width, height), (x, y, crop_width, crop_height));
+                        image = image.crop_imm(x, y, crop_width, crop_height);
+                    }
+                }
+                Augmentation::ColorJitter {
+                    brightness,
+                    contrast,
+                    saturation,
+                    prob,
+                } => {
+                    if rng.gen::<f32>() < prob {
+                        let brightness_shift = rng.gen_range(-brightness.abs()..=brightness.abs()) * 255.0;
+                        let contrast_factor = 1.0 + rng.gen_range(-contrast.abs()..=contrast.abs());
+                        let saturation_factor = 1.0 + rng.gen_range(-saturation.abs()..=saturation.abs());
+                        image = color_jitter(&image, brightness_shift, contrast_factor, saturation_factor)?;
+                    }
+                }
+            }
+        }
+
+        Ok((image, labels))
+    }
+}
+
+impl Task for AugmentationPipeline {
+    type Input = (DynamicImage, Vec<Detection>);
+    type Output = Vec<(DynamicImage, Vec<Detection>)>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (image, labels) = input;
+        let mut rng = self.rng.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        (0..self.variants)
+            .map(|_| self.augment(&mut rng, &image, &labels))
+            .collect()
+    }
+}
+
+fn rotate(image: &DynamicImage, labels: &[Detection], angle: f32) -> Result<(DynamicImage, Vec<Detection>), ProcessingError> {
+    let (width, height) = image.dimensions();
+    let (w, h) = (width as f32, height as f32);
+    let mat = image_to_mat(image)?;
+
+    let center = core::Point2f::new(w / 2.0, h / 2.0);
+    let matrix = imgproc::get_rotation_matrix_2d(center, angle as f64, 1.0)?;
+    let mut rotated = core::Mat::default();
+    imgproc::warp_affine(
+        &mat,
+        &mut rotated,
+        &matrix,
+        mat.size()?,
+        imgproc::INTER_LINEAR,
+        core::BORDER_CONSTANT,
+        core::Scalar::default(),
+    )?;
+
+    let mut m = [0.0f32; 6];
+    for (index, value) in m.iter_mut().enumerate() {
+        *value = *matrix.at_2d::<f64>(index as i32 / 3, index as i32 % 3)? as f32;
+    }
+
+    // Rotate all four corners and keep their axis-aligned bounds
+    let rotated_labels = labels
+        .iter()
+        .filter_map(|label| {
+            let (x1, y1, x2, y2) = to_corners(label, w, h);
+            let corners = [(x1, y1), (x2, y1), (x1, y2), (x2, y2)];
+            let xs = corners.map(|(x, y)| m[0] * x + m[1] * y + m[2]);
+            let ys = corners.map(|(x, y)| m[3] * x + m[4] * y + m[5]);
+            from_corners(
+                label,
+                (
+                    xs.iter().copied().fold(f32::MAX, f32::min),
+                    ys.iter().copied().fold(f32::MAX, f32::min),
+                    xs.iter().copied().fold(f32::MIN, f32::max),
+                    ys.iter().copied().fold(f32::MIN, f32::max),
+                ),
+                w,
+                h,
+            )
+        })
+        .collect();
+
+    Ok((mat_to_image(&rotated)?, rotated_labels))
+}
+
+fn color_jitter(
+    image: &DynamicImage,
+    brightness_shift: f32,
+    contrast_factor: f32,
+    saturation_factor: f32,
+) -> Result<DynamicImage, ProcessingError> {
+    let mat = image_to_mat(image)?;
+    let mut adjusted = core::Mat::default();
+    core::convert_scale_abs(&mat, &mut adjusted, contrast_factor as f64, brightness_shift as f64)?;
+
+    let mut hsv = core::Mat::default();
+    imgproc::cvt_color(&adjusted, &mut hsv, imgproc::COLOR_BGR2HSV, 0)?;
+    let mut channels = core::Vector::<core::Mat>::new();
+    core::split(&hsv, &mut channels)?;
+    let mut saturated = core::Mat::default();
+    channels.get(1)?.convert_to(&mut saturated, -1, saturation_factor as f64, 0.0)?;
+    channels.set(1, saturated)?;
+    core::merge(&channels, &mut hsv)?;
+
+    let mut bgr = core::Mat::default();
+    imgproc::cvt_color(&hsv, &mut bgr, imgproc::COLOR_HSV2BGR, 0)?;
+    mat_to_image(&bgr)
+}
+
+fn crop_labels(labels: &[Detection], size: (u32, u32), crop: (u32, u32, u32, u32)) -> Vec<Detection> {
+    let (w, h) = (size.0 as f32, size.1 as f32);
+    let (x, y, crop_width, crop_height) = (crop.0 as f32, crop.1 as f32, crop.2 as f32, crop.3 as f32);
+    labels
+        .iter()
+        .filter_map(|label| {
+            let (x1, y1, x2, y2) = to_corners(label, w, h);
+            from_corners(label, (x1 - x, y1 - y, x2 - x, y2 - y), crop_width, crop_height)
+        })
+        .collect()
+}
+
+pub(crate) fn to_corners(label: &Detection, width: f32, height: f32) -> (f32, f32, f32, f32) {
+    (
+        (label.x_center - label.width / 2.0) * width,
+        (label.y_center - label.height / 2.0) * height,
+        (label.x_center + label.width / 2.0) * width,
+        (label.y_center + label.height / 2.0) * height,
+    )
+}
+
+// Clips pixel corners to the image and drops boxes that end up empty
+pub(crate) fn from_corners(
+    label: &Detection,
+    corners: (f32, f32, f32, f32),
+    width: f32,
+    height: f32,
+) -> Option<Detection> {
+    let x1 = corners.0.clamp(0.0, width);
+    let y1 = corners.1.clamp(0.0, height);
+    let x2 = corners.2.clamp(0.0, width);
+    let y2 = corners.3.clamp(0.0, height);
+    if x2 - x1 < 1.0 || y2 - y1 < 1.0 {
+        return None;
+    }
+    Some(Detection {
+        x_center: (x1 + x2) / 2.0 / width,
+        y_center: (y1 + y2) / 2.0 / height,
+        width: (x2 - x1) / width,
+        height: (y2 - y1) / height,
+        ..*label
+    })
+}
+
+#[derive(Clone)]
+pub struct MosaicAugmentation {
+    size: (u32, u32),
+    rng: Arc<Mutex<StdRng>>,
+}
+
+#[allow(dead_code)]
+impl MosaicAugmentation {
+    pub fn new(size: (u32, u32)) -> Self {
+        Self {
+            size: (size.0.max(2), size.1.max(2)),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+}
+
+impl Task for MosaicAugmentation {
+    type Input = [(DynamicImage, Vec<Detection>); 4];
+    type Output = (DynamicImage, Vec<Detection>);
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let (width, height) = self.size;
+        let (split_x, split_y) = {
+            let mut rng = self.rng.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+            (
+                rng.gen_range(width / 4..=width * 3 / 4).clamp(1, width - 1),
+                rng.gen_range(height / 4..=height * 3 / 4).clamp(1, height - 1),
+            )
+        };
+        let quadrants = [
+            (0, 0, split_x, split_y),
+            (split_x, 0, width - split_x, split_y),
+            (0, split_y, split_x, height - split_y),
+            (split_x, split_y, width - split_x, height - split_y),
+        ];
+
+        let mut canvas = image::RgbImage::new(width, height);
+        let mut labels = Vec::new();
+
+        for ((image, image_labels), (qx, qy, qw, qh)) in input.iter().zip(quadrants) {
+            // Scale to cover the quadrant, then center-crop; boxes outside the crop are clipped
+            let (image_width, image_height) = image.dimensions();
+            let scale = (qw as f32 / image_width as f32).max(qh as f32 / image_height as f32);
+            let scaled_width = image_width as f32 * scale;
+            let scaled_height = image_height as f32 * scale;
+            let offset_x = (scaled_width - qw as f32) / 2.0;
+            let offset_y = (scaled_height - qh as f32) / 2.0;
+
+            let tile = image.resize_to_fill(qw, qh, image::imageops::FilterType::Triangle).to_rgb8();
+            image::imageops::overlay(&mut canvas, &tile, qx as i64, qy as i64);
+
+            for label in image_labels {
+                let (x1, y1, x2, y2) = to_corners(label, scaled_width, scaled_height);
+                let corners = (x1 - offset_x, y1 - offset_y, x2 - offset_x, y2 - offset_y);
+                if let Some(clipped) = from_corners(label, corners, qw as f32, qh as f32) {
+                    labels.push(Detection {
+                        x_center: (qx as f32 + clipped.x_center * qw as f32) / width as f32,
+                        y_center: (qy as f32 + clipped.y_center * qh as f32) / height as f32,
+                        width: clipped.width * qw as f32 / width as f32,
+                        height: clipped.height * qh as f32 / height as f32,
+                        ..clipped
+                    });
+                }
+            }
+        }
+
+        Ok((DynamicImage::ImageRgb8(canvas), labels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn luma_histogram(image: &DynamicImage) -> [u32; 256] {
+        let mut histogram = [0; 256];
+        for pixel in image.to_luma8().pixels() {
+            histogram[pixel.0[0] as usize] += 1;
+        }
+        histogram
+    }
+
+    #[test]
+    fn color_jitter_changes_the_histogram() {
+        // A gradient, so every brightness level is populated before the shift
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 3) as u8, (y * 3) as u8, 128])
+        }));
+        let output = color_jitter(&input, 30.0, 1.2, 1.5).expect("color jitter");
+
+        assert_eq!(output.dimensions(), input.dimensions());
+        assert_ne!(luma_histogram(&output), luma_histogram(&input));
+    }
+}
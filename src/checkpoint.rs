@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use serde_json::json;
+use crate::{hash_image, ProcessingError};
+
+/// Tracks which images have been annotated so an interrupted run can resume where it stopped.
+/// Images are identified by the SHA-256 of their contents, so renamed or moved files are still
+/// recognised; inputs with no file on disk (video frames) fall back to their path.
+///
+/// The file is rewritten every `SAVE_INTERVAL` completions rather than after each one; call
+/// `save` once the run ends to write the rest.
+#[derive(Debug, Clone)]
+pub struct CheckpointManager {
+    path: PathBuf,
+    completed: Arc<Mutex<HashSet<String>>>,
+    // Hashes computed by `is_completed`, kept until `mark_completed` so each file is hashed once
+    pending: Arc<Mutex<HashMap<String, String>>>,
+    unsaved: Arc<AtomicUsize>,
+}
+
+const SAVE_INTERVAL: usize = 64;
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl CheckpointManager {
+    /// Reads an existing checkpoint, or starts an empty one if `path` does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, ProcessingError> {
+        let completed = if path.exists() {
+            let document: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| ProcessingError::Io(e.into()))?;
+            document["completed"]
+                .as_array()
+                .map(|paths| paths.iter().filter_map(|p| p.as_str()).map(str::to_string).collect())
+                .unwrap_or_default()
+        } else {
+            HashSet::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            completed: Arc::new(Mutex::new(completed)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            unsaved: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.completed.lock().map(|completed| completed.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_completed(&self, image_path: &str) -> bool {
+        let cached = self.pending.lock().ok().and_then(|pending| pending.get(image_path).cloned());
+        let identity = cached.unwrap_or_else(|| identity(image_path));
+        let done = self
+            .completed
+            .lock()
+            // Checkpoints written before hashing was introduced list plain paths
+            .map(|completed| completed.contains(&identity) || completed.contains(image_path))
+            .unwrap_or(false);
+        if !done {
+            if let Ok(mut pending) = self.pending.lock() {
+                pending.insert(image_path.to_string(), identity);
+            }
+        }
+        done
+    }
+
+    pub fn mark_completed(&self, image_path: &str) -> Result<(), ProcessingError> {
+        let cached = self
+            .pending
+            .lock()
+            .map_err(|e| ProcessingError::Lock(e.to_string()))?
+            .remove(image_path);
+        let identity = cached.unwrap_or_else(|| identity(image_path));
+        if !self.completed.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?.insert(identity) {
+            return Ok(());
+        }
+
+        if (self.unsaved.fetch_add(1, Ordering::Relaxed) + 1) % SAVE_INTERVAL == 0 {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Writes every completion recorded so far.
+    pub fn save(&self) -> Result<(), ProcessingError> {
+        let contents = {
+            let completed = self.completed.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+            let mut paths: Vec<&String> = completed.iter().collect();
+            paths.sort();
+            serde_json::to_vec_pretty(&json!({ "completed": paths })).map_err(|e| ProcessingError::Io(e.into()))?
+        };
+
+        // Written beside the target and renamed over it, so a crash never leaves a truncated checkpoint
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn identity(image_path: &str) -> String {
+    hash_image(Path::new(image_path)).unwrap_or_else(|_| image_path.to_string())
+}
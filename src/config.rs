@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use serde::Deserialize;
+use crate::{BackendTarget, ModelFormat};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub model: ModelFormat,
+    pub input_width: i32,
+    pub input_height: i32,
+    pub input_dir: String,
+    pub output_dir: String,
+    pub workers: usize,
+    /// Images per forward pass; larger batches trade latency for GPU throughput.
+    pub batch_size: usize,
+    pub confidence_threshold: f32,
+    pub nms_threshold: f32,
+    pub extensions: Vec<String>,
+    pub backend: BackendTarget,
+    /// Runs one blank frame through the model at startup, so the first image is not slowed down.
+    pub warmup: bool,
+    pub write_metadata: bool,
+    /// Appends each detection's confidence as a sixth column of the YOLO label files.
+    pub write_confidence: bool,
+    /// Also writes a copy of each image with its boxes drawn on, for spot checks.
+    pub preview_dir: Option<String>,
+    /// Label file name pattern, see `OutputTemplate`.
+    pub output_template: String,
+    /// Serves Prometheus metrics on this port while the run is in progress.
+    pub metrics_port: Option<u16>,
+    /// Interface the metrics endpoint binds to; set "0.0.0.0" to serve it on every interface.
+    pub metrics_address: IpAddr,
+    /// Bin width of the confidence histogram reported at the end of a run, e.g. 0.1.
+    pub confidence_histogram: Option<f32>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            model: ModelFormat::Darknet {
+                cfg: "yolov3.cfg".to_string(),
+                weights: "yolov3.weights".to_string(),
+            },
+            input_width: 416,
+            input_height: 416,
+            input_dir: "./screenshots".to_string(),
+            output_dir: "./output/labels".to_string(),
+            workers: 4,
+            batch_size: 1,
+            confidence_threshold: 0.5,
+            nms_threshold: 0.4,
+            extensions: vec!["png".to_string()],
+            backend: BackendTarget::Cpu,
+            warmup: false,
+            write_metadata: false,
+            write_confidence: false,
+            preview_dir: None,
+            output_template: "{stem}.txt".to_string(),
+            metrics_port: None,
+            metrics_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            confidence_histogram: None,
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use glob::glob;
+use crate::{coco_document, find_image_for_label, format_yolo_labels, read_yolo_labels, voc_xml, ClassMap, Detection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LabelFormat {
+    /// One `<stem>.txt` per image with normalized `class cx cy w h` lines.
+    Yolo,
+    /// A single `instances_train.json`; every `*.json` in the input directory is read.
+    Coco,
+    /// One Pascal VOC `<stem>.xml` per image.
+    Voc,
+}
+
+struct LabeledImage {
+    file_name: String,
+    // Unknown for YOLO labels whose image cannot be found
+    size: Option<(u32, u32)>,
+    labels: Vec<Detection>,
+}
+
+/// Rewrites every label file in `input_dir` as `output_format` under `output_dir`. COCO and VOC
+/// names are mapped to IDs through `class_map`; names it does not know must be numeric IDs.
+pub fn convert_labels(
+    input_format: LabelFormat,
+    output_format: LabelFormat,
+    input_dir: &Path,
+    output_dir: &Path,
+    class_map: &ClassMap,
+) -> Result<(), Box<dyn Error>> {
+    // Only YOLO output can do without the image size
+    let needs_size = output_format != LabelFormat::Yolo;
+    let images = match input_format {
+        LabelFormat::Yolo => read_yolo(input_dir, needs_size)?,
+        LabelFormat::Coco => read_coco(input_dir, class_map)?,
+        LabelFormat::Voc => read_voc(input_dir, class_map)?,
+    };
+
+    fs::create_dir_all(output_dir)?;
+    match output_format {
+        LabelFormat::Yolo => {
+            for image in &images {
+                let label_path = output_dir.join(format!("{}.txt", stem(&image.file_name)));
+                fs::write(label_path, format_yolo_labels(&image.labels, false))?;
+            }
+        }
+        LabelFormat::Coco => {
+            let mut records = Vec::with_capacity(images.len());
+            for image in &images {
+                let (width, height) = required_size(image)?;
+                records.push((image.file_name.as_str(), width, height, image.labels.as_slice()));
+            }
+            let document = coco_document(&records, Some(class_map));
+            fs::write(output_dir.join("instances_train.json"), serde_json::to_vec_pretty(&document)?)?;
+        }
+        LabelFormat::Voc => {
+            for image in &images {
+                let (width, height) = required_size(image)?;
+                let xml = voc_xml(&image.file_name, width, height, &image.labels, Some(class_map));
+                fs::write(output_dir.join(format!("{}.xml", stem(&image.file_name))), xml)?;
+            }
+        }
+    }
+    tracing::info!(images = images.len(), output = %output_dir.display(), "Labels converted");
+    Ok(())
+}
+
+fn read_yolo(input_dir: &Path, needs_size: bool) -> Result<Vec<LabeledImage>, Box<dyn Error>> {
+    let mut images = Vec::new();
+    for label_path in sorted_files(input_dir, "txt")? {
+        let labels = read_yolo_labels(&label_path)?;
+        let image_path = find_image_for_label(&label_path);
+        let size = match (&image_path, needs_size) {
+            (Some(image_path), true) => Some(image::image_dimensions(image_path)?),
+            _ => None,
+        };
+        let file_name = image_path
+            .as_deref()
+            .unwrap_or(&label_path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        images.push(LabeledImage { file_name, size, labels });
+    }
+    Ok(images)
+}
+
+fn read_coco(input_dir: &Path, class_map: &ClassMap) -> Result<Vec<LabeledImage>, Box<dyn Error>> {
+    let mut images = Vec::new();
+    for json_path in sorted_files(input_dir, "json")? {
+        let document: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json_path)?)?;
+        let categories: HashMap<u64, &str> = document["categories"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|category| Some((category["id"].as_u64()?, category["name"].as_str()?)))
+            .collect();
+
+        let mut by_id: BTreeMap<u64, LabeledImage> = BTreeMap::new();
+        for entry in document["images"].as_array().into_iter().flatten() {
+            let (Some(id), Some(file_name)) = (entry["id"].as_u64(), entry["file_name"].as_str()) else {
+                continue;
+            };
+            let size = entry["width"].as_u64().zip(entry["height"].as_u64());
+            by_id.insert(
+                id,
+                LabeledImage {
+                    file_name: file_name.to_string(),
+                    size: size.map(|(width, height)| (width as u32, height as u32)),
+                    labels: Vec::new(),
+                },
+            );
+        }
+
+        for annotation in document["annotations"].as_array().into_iter().flatten() {
+            let Some(image) = annotation["image_id"].as_u64().and_then(|id| by_id.get_mut(&id)) else {
+                continue;
+            };
+            let Some((width, height)) = image.size else { continue };
+            let bbox: Vec<f32> = annotation["bbox"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_f64().map(|v| v as f32))
+                .collect();
+            if bbox.len() != 4 {
+                continue;
+            }
+            let category_id = annotation["category_id"].as_u64().unwrap_or(0);
+            // Our own COCO output uses the class ID as category ID, so that is the fallback
+            let class_id = categories
+                .get(&category_id)
+                .and_then(|name| resolve_class(name, class_map))
+                .unwrap_or(category_id as u32);
+            let (width, height) = (width as f32, height as f32);
+            image.labels.push(Detection {
+                class_id,
+                confidence: annotation["score"].as_f64().unwrap_or(1.0) as f32,
+                x_center: (bbox[0] + bbox[2] / 2.0) / width,
+                y_center: (bbox[1] + bbox[3] / 2.0) / height,
+                width: bbox[2] / width,
+                height: bbox[3] / height,
+            });
+        }
+        images.extend(by_id.into_values());
+    }
+    Ok(images)
+}
+
+fn read_voc(input_dir: &Path, class_map: &ClassMap) -> Result<Vec<LabeledImage>, Box<dyn Error>> {
+    let mut images = Vec::new();
+    for xml_path in sorted_files(input_dir, "xml")? {
+        let xml = fs::read_to_string(&xml_path)?;
+        let file_name = xml_tag(&xml, "filename")
+            .map(xml_unescape)
+            .unwrap_or_else(|| format!("{}.png", stem(&xml_path.display().to_string())));
+        let width: f32 = xml_tag(&xml, "width").and_then(|v| v.trim().parse().ok()).unwrap_or(0.0);
+        let height: f32 = xml_tag(&xml, "height").and_then(|v| v.trim().parse().ok()).unwrap_or(0.0);
+        if width <= 0.0 || height <= 0.0 {
+            return Err(format!("{} has no image size", xml_path.display()).into());
+        }
+
+        let mut labels = Vec::new();
+        for object in xml_blocks(&xml, "object") {
+            let name = xml_tag(object, "name").map(xml_unescape).unwrap_or_default();
+            let class_id = resolve_class(&name, class_map)
+                .ok_or_else(|| format!("{}: unknown class {:?}", xml_path.display(), name))?;
+            let coordinate = |tag: &str| xml_tag(object, tag).and_then(|v| v.trim().parse::<f32>().ok());
+            let (Some(xmin), Some(ymin), Some(xmax), Some(ymax)) =
+                (coordinate("xmin"), coordinate("ymin"), coordinate("xmax"), coordinate("ymax"))
+            else {
+                continue;
+            };
+            labels.push(Detection {
+                class_id,
+                confidence: 1.0,
+                x_center: (xmin + xmax) / 2.0 / width,
+                y_center: (ymin + ymax) / 2.0 / height,
+                width: (xmax - xmin) / width,
+                height: (ymax - ymin) / height,
+            });
+        }
+        images.push(LabeledImage {
+            file_name,
+            size: Some((width as u32, height as u32)),
+            labels,
+        });
+    }
+    Ok(images)
+}
+
+fn sorted_files(directory: &Path, extension: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths: Vec<PathBuf> = glob(&format!("{}/*.{}", directory.display(), extension))?
+        .filter_map(Result::ok)
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn required_size(image: &LabeledImage) -> Result<(u32, u32), Box<dyn Error>> {
+    image
+        .size
+        .ok_or_else(|| format!("No image found for {}, its size is needed for absolute coordinates", image.file_name).into())
+}
+
+// Names written without a class map are the numeric IDs themselves
+fn resolve_class(name: &str, class_map: &ClassMap) -> Option<u32> {
+    class_map
+        .names
+        .iter()
+        .position(|known| known == name)
+        .map(|index| index as u32)
+        .or_else(|| name.parse().ok())
+}
+
+fn stem(file_name: &str) -> &str {
+    Path::new(file_name).file_stem().and_then(|s| s.to_str()).unwrap_or(file_name)
+}
+
+// Just enough XML for the flat layout Pascal VOC uses; attributes and CDATA are not supported
+fn xml_tag<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    xml_blocks(xml, tag).into_iter().next()
+}
+
+fn xml_blocks<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut blocks = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let content = &rest[start + open.len()..];
+        let Some(end) = content.find(&close) else { break };
+        blocks.push(&content[..end]);
+        rest = &content[end + close.len()..];
+    }
+    blocks
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,123 @@
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use tokio::task::JoinHandle;
+
+// Upper bounds in seconds, matching the Prometheus client defaults
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Process-wide counters scraped by `MetricsServer`; every update is a single atomic add.
+pub struct PipelineCounters {
+    images_processed: AtomicU64,
+    images_errored: AtomicU64,
+    detections: AtomicU64,
+    // Per-bucket counts, made cumulative when rendered
+    inference_buckets: [AtomicU64; BUCKETS.len()],
+    inference_count: AtomicU64,
+    inference_sum_micros: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+pub static COUNTERS: PipelineCounters = PipelineCounters {
+    images_processed: ZERO,
+    images_errored: ZERO,
+    detections: ZERO,
+    inference_buckets: [ZERO; BUCKETS.len()],
+    inference_count: ZERO,
+    inference_sum_micros: ZERO,
+};
+
+impl PipelineCounters {
+    pub fn record_success(&self, detections: usize) {
+        self.images_processed.fetch_add(1, Ordering::Relaxed);
+        self.detections.fetch_add(detections as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.images_errored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_inference(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.inference_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.inference_count.fetch_add(1, Ordering::Relaxed);
+        self.inference_sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Renders the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let counters = [
+            ("images_processed_total", "Images annotated successfully.", &self.images_processed),
+            ("images_errored_total", "Images that failed to load or run through the detector.", &self.images_errored),
+            ("detections_total", "Bounding boxes produced across all images.", &self.detections),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            let _ = writeln!(text, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        let _ = writeln!(text, "# HELP inference_duration_seconds Time spent running the detector on one image.");
+        let _ = writeln!(text, "# TYPE inference_duration_seconds histogram");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&self.inference_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(text, "inference_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+        }
+        let count = self.inference_count.load(Ordering::Relaxed);
+        let sum = self.inference_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(text, "inference_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(text, "inference_duration_seconds_sum {}", sum);
+        let _ = writeln!(text, "inference_duration_seconds_count {}", count);
+        text
+    }
+}
+
+/// Serves `COUNTERS` on `/metrics` for scraping by Prometheus.
+pub struct MetricsServer;
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl MetricsServer {
+    /// Binds on localhost only; use `start_on` to expose the endpoint to other machines.
+    #[allow(dead_code)]
+    pub fn start(port: u16) -> JoinHandle<()> {
+        Self::start_on(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+    }
+
+    /// A bind failure is logged rather than aborting the annotation run.
+    pub fn start_on(addr: SocketAddr) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+            match Server::try_bind(&addr) {
+                Ok(builder) => {
+                    tracing::info!(%addr, "Serving Prometheus metrics on /metrics");
+                    if let Err(e) = builder.serve(make_service).await {
+                        tracing::error!(error = %e, "Metrics server stopped");
+                    }
+                }
+                Err(e) => tracing::error!(error = %e, %addr, "Failed to bind metrics endpoint"),
+            }
+        })
+    }
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = if request.uri().path() == "/metrics" {
+        Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(COUNTERS.render()))
+    } else {
+        Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
+    };
+    Ok(response.unwrap_or_default())
+}
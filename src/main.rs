@@ -1,37 +1,121 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::fmt;
 use std::fs::{self, File};
-use std::io::Write;
-use std::path::Path;
+use std::io::{BufWriter, IsTerminal, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tracing::Instrument;
+use async_trait::async_trait;
+use base64::Engine;
+use clap::Parser;
 use glob::glob;
-use image::{DynamicImage, GenericImageView};
-use opencv::{core, dnn, prelude::*};
+use image::DynamicImage;
+#[cfg(feature = "inference")]
+use image::GenericImageView;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use notify::Watcher;
+#[cfg(feature = "inference")]
+use opencv::{core, dnn, imgproc, prelude::*, videoio, ximgproc};
+use prost::Message;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "inference")]
+mod augment;
+mod checkpoint;
+#[cfg(feature = "inference")]
+mod config;
+mod convert;
+mod exporter;
+mod metrics;
+mod visualize;
 
 #[derive(Debug)]
-struct ProcessingError(String);
+enum ProcessingError {
+    #[cfg(feature = "inference")]
+    OpenCv(opencv::Error),
+    Image(image::ImageError),
+    Io(std::io::Error),
+    Lock(String),
+    InvalidDetection(String),
+}
 
 impl fmt::Display for ProcessingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Processing error: {}", self.0)
+        match self {
+            #[cfg(feature = "inference")]
+            ProcessingError::OpenCv(e) => write!(f, "OpenCV error: {}", e.message),
+            ProcessingError::Image(e) => write!(f, "Image error: {}", e),
+            ProcessingError::Io(e) => write!(f, "I/O error: {}", e),
+            ProcessingError::Lock(message) => write!(f, "Lock poisoned: {}", message),
+            ProcessingError::InvalidDetection(message) => write!(f, "Invalid detection: {}", message),
+        }
     }
 }
 
-impl Error for ProcessingError {}
+impl Error for ProcessingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "inference")]
+            ProcessingError::OpenCv(e) => Some(e),
+            ProcessingError::Image(e) => Some(e),
+            ProcessingError::Io(e) => Some(e),
+            ProcessingError::Lock(_) | ProcessingError::InvalidDetection(_) => None,
+        }
+    }
+}
 
+#[cfg(feature = "inference")]
 impl From<opencv::Error> for ProcessingError {
     fn from(error: opencv::Error) -> Self {
-        ProcessingError(error.message)
+        ProcessingError::OpenCv(error)
+    }
+}
+
+impl From<image::ImageError> for ProcessingError {
+    fn from(error: image::ImageError) -> Self {
+        ProcessingError::Image(error)
+    }
+}
+
+impl From<std::io::Error> for ProcessingError {
+    fn from(error: std::io::Error) -> Self {
+        ProcessingError::Io(error)
     }
 }
 
+fn invalid_path(path: &str) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("Invalid image path {}", path),
+    ))
+}
+
 trait Task: Send + Sync + 'static {
     type Input;
     type Output;
     type Error: Error + Send;
 
     fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error>;
+
+    /// Number of inputs `ProcessingSystem` collects before calling `process_batch`.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Returns one result per input, in input order.
+    fn process_batch(&self, inputs: Vec<Self::Input>) -> Vec<Result<Self::Output, Self::Error>> {
+        inputs.into_iter().map(|input| self.process(input)).collect()
+    }
 }
 
 trait DataSource: Send + Sync + 'static {
@@ -39,198 +123,5364 @@ trait DataSource: Send + Sync + 'static {
     type Error: Error + Send;
 
     fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>>;
+
+    fn len(&self) -> Option<usize> {
+        None
+    }
+}
+
+#[async_trait]
+trait AsyncDataSource: Send + Sync + 'static {
+    type Item;
+    type Error: Error + Send;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>>;
+
+    fn len(&self) -> Option<usize> {
+        None
+    }
+}
+
+// Synchronous sources run on the worker thread without stalling the rest of the runtime.
+// block_in_place requires the multi-threaded runtime that #[tokio::main] provides.
+#[async_trait]
+impl<D: DataSource> AsyncDataSource for D
+where
+    D::Item: Send,
+{
+    type Item = D::Item;
+    type Error = D::Error;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        tokio::task::block_in_place(|| DataSource::get_data(self))
+    }
+
+    fn len(&self) -> Option<usize> {
+        DataSource::len(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Detection {
+    class_id: u32,
+    confidence: f32,
+    x_center: f32,
+    y_center: f32,
+    width: f32,
+    height: f32,
+}
+
+#[cfg(feature = "inference")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+enum ModelFormat {
+    Darknet { cfg: String, weights: String },
+    Onnx { path: String },
+    TensorFlow { pb: String, pbtxt: String },
+    Caffe { proto: String, caffemodel: String },
+}
+
+#[cfg(feature = "inference")]
+impl ModelFormat {
+    /// Returns `(config, weights)` file names; single-file formats have an empty config.
+    fn model_files(&self) -> (&str, &str) {
+        match self {
+            ModelFormat::Darknet { cfg, weights } => (cfg, weights),
+            ModelFormat::Onnx { path } => ("", path),
+            ModelFormat::TensorFlow { pb, pbtxt } => (pbtxt, pb),
+            ModelFormat::Caffe { proto, caffemodel } => (proto, caffemodel),
+        }
+    }
+}
+
+#[cfg(feature = "inference")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BackendTarget {
+    Cpu,
+    Cuda,
+    CudaFp16,
+    OpenCL,
+}
+
+#[cfg(feature = "inference")]
+impl BackendTarget {
+    fn backend_and_target(self) -> (i32, i32) {
+        match self {
+            BackendTarget::Cpu => (dnn::DNN_BACKEND_OPENCV, dnn::DNN_TARGET_CPU),
+            BackendTarget::Cuda => (dnn::DNN_BACKEND_CUDA, dnn::DNN_TARGET_CUDA),
+            BackendTarget::CudaFp16 => (dnn::DNN_BACKEND_CUDA, dnn::DNN_TARGET_CUDA_FP16),
+            BackendTarget::OpenCL => (dnn::DNN_BACKEND_OPENCV, dnn::DNN_TARGET_OPENCL),
+        }
+    }
+}
+
+#[cfg(feature = "inference")]
+#[derive(Debug, Clone)]
+struct ClassThresholds {
+    default: f32,
+    per_class: HashMap<u32, f32>,
+}
+
+#[cfg(feature = "inference")]
+impl ClassThresholds {
+    fn new(default: f32) -> Self {
+        Self {
+            default,
+            per_class: HashMap::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn with_class(mut self, class_id: u32, threshold: f32) -> Self {
+        self.per_class.insert(class_id, threshold);
+        self
+    }
+
+    fn get(&self, class_id: u32) -> f32 {
+        self.per_class.get(&class_id).copied().unwrap_or(self.default)
+    }
+
+    fn min(&self) -> f32 {
+        self.per_class.values().copied().fold(self.default, f32::min)
+    }
+}
+
+#[cfg(feature = "inference")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[allow(dead_code)]
+enum ColorSpace {
+    #[default]
+    Bgr,
+    /// Replicated to three channels, since the network still expects a 3-channel input.
+    Grayscale,
+    Hsv,
+    Lab,
+}
+
+#[cfg(feature = "inference")]
+impl ColorSpace {
+    fn convert(self, bgr: &core::Mat) -> Result<core::Mat, ProcessingError> {
+        let code = match self {
+            ColorSpace::Bgr => return Ok(bgr.try_clone()?),
+            ColorSpace::Grayscale => {
+                let mut gray = core::Mat::default();
+                imgproc::cvt_color(bgr, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+                let mut replicated = core::Mat::default();
+                imgproc::cvt_color(&gray, &mut replicated, imgproc::COLOR_GRAY2BGR, 0)?;
+                return Ok(replicated);
+            }
+            ColorSpace::Hsv => imgproc::COLOR_BGR2HSV,
+            ColorSpace::Lab => imgproc::COLOR_BGR2Lab,
+        };
+        let mut converted = core::Mat::default();
+        imgproc::cvt_color(bgr, &mut converted, code, 0)?;
+        Ok(converted)
+    }
+}
+
+#[cfg(feature = "inference")]
+#[derive(Debug, Clone, Copy)]
+struct BlobConfig {
+    scale: f64,
+    mean: (f64, f64, f64),
+    swap_rb: bool,
+    crop: bool,
+    /// Pads to the network size instead of stretching, so sprites keep their aspect ratio.
+    letterbox: bool,
+    /// Applied after resizing; `swap_rb` then swaps the first and third channels of the converted image.
+    color_space: ColorSpace,
+}
+
+#[cfg(feature = "inference")]
+impl Default for BlobConfig {
+    fn default() -> Self {
+        Self {
+            scale: 1.0 / 255.0,
+            mean: (0.0, 0.0, 0.0),
+            swap_rb: true,
+            crop: false,
+            letterbox: false,
+            color_space: ColorSpace::Bgr,
+        }
+    }
 }
 
+#[cfg(feature = "inference")]
 #[derive(Clone)]
 struct ObjectDetectionTask {
     net: Arc<Mutex<dnn::Net>>,
     width: i32,
     height: i32,
+    blob_config: BlobConfig,
+    thresholds: ClassThresholds,
+    nms_threshold: f32,
+    min_box_area: f32,
+    aspect_ratio_bounds: Option<(f32, f32)>,
+    roi: Option<core::Rect>,
+    background: Option<Arc<BackgroundSubtractor>>,
+    batch_size: usize,
+    warmup: bool,
+    border_margin: Option<f32>,
 }
 
+#[cfg(feature = "inference")]
 impl ObjectDetectionTask {
-    fn new(cfg_path: &str, weights_path: &str, width: i32, height: i32) -> Result<Self, Box<dyn Error>> {
-        let net = dnn::read_net_from_darknet(cfg_path, weights_path)?;
+    fn new(
+        cfg_path: &str,
+        weights_path: &str,
+        width: i32,
+        height: i32,
+        blob_config: BlobConfig,
+        confidence_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let format = ModelFormat::Darknet {
+            cfg: cfg_path.to_string(),
+            weights: weights_path.to_string(),
+        };
+        Self::from_model(format, width, height, blob_config, confidence_threshold, nms_threshold)
+    }
+
+    fn from_model(
+        format: ModelFormat,
+        width: i32,
+        height: i32,
+        blob_config: BlobConfig,
+        confidence_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let net = match &format {
+            ModelFormat::Darknet { cfg, weights } => dnn::read_net_from_darknet(cfg, weights)?,
+            ModelFormat::Onnx { path } => dnn::read_net_from_onnx(path)?,
+            ModelFormat::TensorFlow { pb, pbtxt } => dnn::read_net_from_tensorflow(pb, pbtxt)?,
+            ModelFormat::Caffe { proto, caffemodel } => dnn::read_net_from_caffe(proto, caffemodel)?,
+        };
         Ok(Self { 
             net: Arc::new(Mutex::new(net)),
             width, 
-            height 
+            height,
+            blob_config,
+            thresholds: ClassThresholds::new(confidence_threshold),
+            nms_threshold,
+            min_box_area: 0.0,
+            aspect_ratio_bounds: None,
+            roi: None,
+            background: None,
+            batch_size: 1,
+            warmup: false,
+            border_margin: None,
         })
     }
 
-   
-    fn detect_objects(&self, input: &DynamicImage) -> Result<Vec<(u32, f32, f32, f32, f32)>, ProcessingError> {
-        let size = input.dimensions();
-        
-        // Convert image bytes to OpenCV Mat using core::Mat::from_slice
-        let bytes = input.as_bytes();
-        let mat_data = core::Mat::from_slice(bytes)?;
-        let mat = opencv::imgcodecs::imdecode(&mat_data, opencv::imgcodecs::IMREAD_COLOR)?;
-
-        let blob = dnn::blob_from_image(
-            &mat,
-            1.0 / 255.0,
-            core::Size::new(self.width, self.height),
-            core::Scalar::default(),
-            true,
-            false,
-            core::CV_8U,
-        )?;
+    #[allow(dead_code)]
+    fn with_class_thresholds(mut self, thresholds: ClassThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_min_box_area(mut self, min_area_fraction: f32) -> Self {
+        self.min_box_area = min_area_fraction;
+        self
+    }
+
+    /// Keeps detections whose pixel `width / height` lies in `[min, max]`, both inclusive.
+    /// A ratio of `1.0` is a perfect square.
+    #[allow(dead_code)]
+    fn with_aspect_ratio_bounds(mut self, min: f32, max: f32) -> Self {
+        self.aspect_ratio_bounds = Some((min, max));
+        self
+    }
+
+    /// Drops detections that come within `margin` (normalized) of any image edge, since objects
+    /// entering or leaving the frame are only partly visible and make noisy labels.
+    #[allow(dead_code)]
+    fn with_border_exclusion(mut self, margin: f32) -> Self {
+        self.border_margin = Some(margin);
+        self
+    }
+
+    /// Only the pixels inside `roi` reach the network, which keeps HUD overlays from producing
+    /// detections; boxes are still reported relative to the full image.
+    #[allow(dead_code)]
+    fn with_roi(mut self, roi: core::Rect) -> Self {
+        self.roi = Some(roi);
+        self
+    }
+
+    /// Masks out the static background before the ROI crop and blob creation.
+    #[allow(dead_code)]
+    fn with_background_subtractor(mut self, subtractor: BackgroundSubtractor) -> Self {
+        self.background = Some(Arc::new(subtractor));
+        self
+    }
+
+    /// Number of images `ProcessingSystem` groups into one forward pass.
+    fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Runs one forward pass on a blank frame right away, so the first real image does not pay
+    /// for layer initialization. A failed warm-up is only logged.
+    fn with_warmup(mut self, enabled: bool) -> Self {
+        self.warmup = enabled;
+        if enabled {
+            if let Err(e) = self.warm_up() {
+                tracing::warn!(error = %e, "Model warm-up failed");
+            }
+        }
+        self
+    }
+
+    fn with_backend(self, backend: BackendTarget) -> Result<Self, ProcessingError> {
+        let (backend_id, target_id) = backend.backend_and_target();
+        {
+            let mut net = self.net.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+            net.set_preferable_backend(backend_id)?;
+            net.set_preferable_target(target_id)?;
+        }
+        // Switching backends discards the initialized layers
+        if self.warmup {
+            self.warm_up()?;
+        }
+        Ok(self)
+    }
+
+    fn warm_up(&self) -> Result<(), ProcessingError> {
+        let started = Instant::now();
+        let mut mats = core::Vector::<core::Mat>::new();
+        mats.push(core::Mat::new_rows_cols_with_default(
+            self.height,
+            self.width,
+            core::CV_8UC3,
+            core::Scalar::all(0.0),
+        )?);
+        let blob = self.create_blob(&mats)?;
+
+        let mut net = self.net.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        net.set_input(&blob, "", 1.0, core::Scalar::default())?;
+        let mut output_layers = net.get_unconnected_out_layers_names()?;
+        let mut outputs = core::Vector::<core::Mat>::new();
+        net.forward(&mut outputs, &mut output_layers)?;
+        tracing::info!(elapsed_ms = started.elapsed().as_secs_f64() * 1000.0, "Model warmed up");
+        Ok(())
+    }
+
+    fn detect_objects(&self, input: &DynamicImage) -> Result<Vec<Detection>, ProcessingError> {
+        let mut outputs = self.detect_objects_batch(std::slice::from_ref(input))?;
+        outputs
+            .pop()
+            .ok_or_else(|| ProcessingError::InvalidDetection("Network returned no output".to_string()))
+    }
+
+    /// Detects only inside `regions` (pixel rects), each grown to at least the network input
+    /// size for context. Boxes are normalized to the whole image; overlapping crops can report
+    /// the same object twice, so callers usually follow with `suppress_duplicates`.
+    fn detect_in_regions(&self, input: &DynamicImage, regions: &[core::Rect]) -> Result<Vec<Detection>, ProcessingError> {
+        let (img_width, img_height) = input.dimensions();
+        let mut detections = Vec::new();
+        for region in regions {
+            let crop_width = (region.width.max(self.width).max(1) as u32).min(img_width);
+            let crop_height = (region.height.max(self.height).max(1) as u32).min(img_height);
+            let center_x = (region.x + region.width / 2).max(0) as u32;
+            let center_y = (region.y + region.height / 2).max(0) as u32;
+            let x = center_x.saturating_sub(crop_width / 2).min(img_width - crop_width);
+            let y = center_y.saturating_sub(crop_height / 2).min(img_height - crop_height);
+
+            let crop = input.crop_imm(x, y, crop_width, crop_height);
+            for detection in self.detect_objects(&crop)? {
+                detections.push(Detection {
+                    x_center: (x as f32 + detection.x_center * crop_width as f32) / img_width as f32,
+                    y_center: (y as f32 + detection.y_center * crop_height as f32) / img_height as f32,
+                    width: detection.width * crop_width as f32 / img_width as f32,
+                    height: detection.height * crop_height as f32 / img_height as f32,
+                    ..detection
+                });
+            }
+        }
+        Ok(detections)
+    }
+
+    /// Runs one forward pass over all `inputs`; the result holds one entry per input, in order.
+    fn detect_objects_batch(&self, inputs: &[DynamicImage]) -> Result<Vec<Vec<Detection>>, ProcessingError> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut mats = core::Vector::<core::Mat>::new();
+        let mut mappings = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let (mat, mapping) = self.prepare(input)?;
+            mats.push(mat);
+            mappings.push(mapping);
+        }
+
+        let blob = self.create_blob(&mats)?;
 
         // Acquire lock on the network
-        let mut net = self.net.lock().map_err(|e| ProcessingError(e.to_string()))?;
+        let mut net = self.net.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
         
         net.set_input(&blob, "", 1.0, core::Scalar::default())?;
 
         let mut output_layers = net.get_unconnected_out_layers_names()?;
         let mut outputs = core::Vector::<core::Mat>::new();  // Fixed turbofish syntax
         net.forward(&mut outputs, &mut output_layers)?;
+        drop(net);
+
+        let outputs = outputs.iter().map(|output| flatten_output(&output)).collect::<Result<Vec<_>, _>>()?;
+        mappings
+            .iter()
+            .enumerate()
+            .map(|(index, mapping)| self.decode(&outputs, index, inputs.len(), mapping))
+            .collect()
+    }
+
+    /// Runs a single forward pass and reports where the time went. OpenCV only records layer
+    /// timings on the OpenCV backend with the CPU target; other backends report zeros.
+    #[allow(dead_code)]
+    fn profile_inference(&self, input: &DynamicImage) -> Result<InferenceProfile, ProcessingError> {
+        let (mat, _) = self.prepare(input)?;
+        let mut mats = core::Vector::<core::Mat>::new();
+        mats.push(mat);
+        let blob = self.create_blob(&mats)?;
+
+        let mut net = self.net.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        net.set_input(&blob, "", 1.0, core::Scalar::default())?;
+        let mut output_layers = net.get_unconnected_out_layers_names()?;
+        let mut outputs = core::Vector::<core::Mat>::new();
+        net.forward(&mut outputs, &mut output_layers)?;
+
+        // Timings are indexed like the layer names; both leave out the input layer
+        let mut timings = core::Vector::<f64>::new();
+        let total_ticks = net.get_perf_profile(&mut timings)?;
+        let names = net.get_layer_names()?;
+        drop(net);
+
+        let ms_per_tick = 1000.0 / core::get_tick_frequency()?;
+        Ok(InferenceProfile {
+            total_ms: total_ticks as f64 * ms_per_tick,
+            layer_times: names
+                .iter()
+                .zip(timings.iter())
+                .map(|(name, ticks)| (name, ticks * ms_per_tick))
+                .collect(),
+        })
+    }
+
+    fn create_blob(&self, mats: &core::Vector<core::Mat>) -> Result<core::Mat, ProcessingError> {
+        let (mean_r, mean_g, mean_b) = self.blob_config.mean;
+        let blob = dnn::blob_from_images(
+            mats,
+            self.blob_config.scale,
+            core::Size::new(self.width, self.height),
+            core::Scalar::new(mean_r, mean_g, mean_b, 0.0),
+            self.blob_config.swap_rb,
+            self.blob_config.crop,
+            core::CV_32F,
+        )?;
+        if std::env::var_os("SKYFORCE_DEBUG_BLOB").is_some() {
+            log_blob_stats(&blob);
+        }
+        Ok(blob)
+    }
+
+    fn prepare(&self, input: &DynamicImage) -> Result<(core::Mat, BoxMapping), ProcessingError> {
+        let size = input.dimensions();
+        let mat = image_to_mat(input)?;
+        let mat = match &self.background {
+            Some(background) => background.apply(&mat)?,
+            None => mat,
+        };
+
+        let (mat, origin) = match self.roi {
+            Some(roi) => {
+                let roi = clamp_rect(roi, mat.cols(), mat.rows()).ok_or_else(|| {
+                    ProcessingError::InvalidDetection(format!(
+                        "ROI {:?} lies outside the {}x{} image",
+                        roi, size.0, size.1
+                    ))
+                })?;
+                (core::Mat::roi(&mat, roi)?.try_clone()?, (roi.x as f32, roi.y as f32))
+            }
+            None => (mat, (0.0, 0.0)),
+        };
+
+        let (mat, scale, offset) = if self.blob_config.letterbox {
+            let (padded, scale, (pad_x, pad_y)) = letterbox(&mat, core::Size::new(self.width, self.height))?;
+            (
+                padded,
+                (self.width as f32 / scale, self.height as f32 / scale),
+                (pad_x as f32 / scale - origin.0, pad_y as f32 / scale - origin.1),
+            )
+        } else {
+            let crop_size = (mat.cols() as f32, mat.rows() as f32);
+            (mat, crop_size, (-origin.0, -origin.1))
+        };
+        let mat = match self.blob_config.color_space {
+            ColorSpace::Bgr => mat,
+            color_space => color_space.convert(&mat)?,
+        };
+
+        let mapping = BoxMapping {
+            image_size: (size.0 as f32, size.1 as f32),
+            scale,
+            offset,
+        };
+        Ok((mat, mapping))
+    }
+
+    // `outputs` hold the rows of every image in the batch back to back
+    fn decode(
+        &self,
+        outputs: &[core::Mat],
+        image_index: usize,
+        batch_size: usize,
+        mapping: &BoxMapping,
+    ) -> Result<Vec<Detection>, ProcessingError> {
+        let (img_width, img_height) = mapping.image_size;
+        let (scale_x, scale_y) = mapping.scale;
+        let (offset_x, offset_y) = mapping.offset;
+
+        let mut candidates: Vec<(u32, core::Rect, f32)> = Vec::new();
+
+        // Each row is [cx, cy, w, h, objectness, class scores...], normalized to the blob size
+        for output in outputs {
+            let rows_per_image = output.rows() / batch_size as i32;
+            let first_row = rows_per_image * image_index as i32;
+            for row in first_row..first_row + rows_per_image {
+                let data = output.at_row::<f32>(row)?;
+                if data.len() < 6 {
+                    continue;
+                }
+
+                let confidence = data[4];
+                if confidence < self.thresholds.min() {
+                    continue;
+                }
+
+                let (class_id, _) = data[5..]
+                    .iter()
+                    .enumerate()
+                    .fold((0, f32::MIN), |best, (id, &score)| if score > best.1 { (id, score) } else { best });
+
+                if confidence < self.thresholds.get(class_id as u32) {
+                    continue;
+                }
+
+                // Scale back to the original image so NMS works on pixel boxes
+                let box_width = data[2] * scale_x;
+                let box_height = data[3] * scale_y;
+                let left = data[0] * scale_x - offset_x - box_width / 2.0;
+                let top = data[1] * scale_y - offset_y - box_height / 2.0;
+
+                candidates.push((
+                    class_id as u32,
+                    core::Rect::new(left as i32, top as i32, box_width as i32, box_height as i32),
+                    confidence,
+                ));
+            }
+        }
+
+        let mut class_ids: Vec<u32> = candidates.iter().map(|c| c.0).collect();
+        class_ids.sort_unstable();
+        class_ids.dedup();
 
         let mut annotations = Vec::new();
-        // TODO: Implement detection extraction logic
+
+        // Suppress overlapping boxes within each class independently
+        for class_id in class_ids {
+            let class_candidates: Vec<&(u32, core::Rect, f32)> =
+                candidates.iter().filter(|c| c.0 == class_id).collect();
+            let boxes: core::Vector<core::Rect> = class_candidates.iter().map(|c| c.1).collect();
+            let scores: core::Vector<f32> = class_candidates.iter().map(|c| c.2).collect();
+
+            let mut indices = core::Vector::<i32>::new();
+            dnn::nms_boxes(
+                &boxes,
+                &scores,
+                self.thresholds.get(class_id),
+                self.nms_threshold,
+                &mut indices,
+                1.0,
+                0,
+            )?;
+
+            for index in indices.iter() {
+                let (_, rect, confidence) = *class_candidates[index as usize];
+                annotations.push(Detection {
+                    class_id,
+                    confidence,
+                    x_center: (rect.x as f32 + rect.width as f32 / 2.0) / img_width,
+                    y_center: (rect.y as f32 + rect.height as f32 / 2.0) / img_height,
+                    width: rect.width as f32 / img_width,
+                    height: rect.height as f32 / img_height,
+                });
+            }
+        }
+
+        let mut annotations: Vec<Detection> = annotations.into_iter().map(sanitize_detection).collect();
+        annotations.retain(|d| d.width * d.height >= self.min_box_area);
+
+        if let Some(margin) = self.border_margin {
+            annotations.retain(|d| {
+                let (x1, y1, x2, y2) = augment::to_corners(d, 1.0, 1.0);
+                x1 >= margin && y1 >= margin && x2 <= 1.0 - margin && y2 <= 1.0 - margin
+            });
+        }
+
+        if let Some((min_ratio, max_ratio)) = self.aspect_ratio_bounds {
+            annotations.retain(|d| {
+                let pixel_height = d.height * img_height;
+                if pixel_height <= 0.0 {
+                    return false;
+                }
+                let ratio = d.width * img_width / pixel_height;
+                ratio >= min_ratio && ratio <= max_ratio
+            });
+        }
 
         Ok(annotations)
     }
 }
 
-impl Task for ObjectDetectionTask {
-    type Input = DynamicImage;
-    type Output = Vec<(u32, f32, f32, f32, f32)>;
-    type Error = ProcessingError;
-
-    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
-        self.detect_objects(&input)
+// Boxes reaching past the frame edge are cut at the edge rather than shifted, so the visible part keeps its place
+#[cfg(feature = "inference")]
+fn sanitize_detection(d: Detection) -> Detection {
+    let (x1, y1, x2, y2) = augment::to_corners(&d, 1.0, 1.0);
+    let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+    let (y1, y2) = (y1.clamp(0.0, 1.0), y2.clamp(0.0, 1.0));
+    Detection {
+        x_center: (x1 + x2) / 2.0,
+        y_center: (y1 + y2) / 2.0,
+        width: x2 - x1,
+        height: y2 - y1,
+        ..d
     }
 }
 
-#[derive(Clone)]
-struct ImageSource {
-    paths: Vec<String>,
-    index: usize,
+#[cfg(feature = "inference")]
+#[derive(Debug, Clone, Default)]
+struct InferenceProfile {
+    total_ms: f64,
+    /// Per-layer time in network order; fused layers show up with `0.0`.
+    layer_times: Vec<(String, f64)>,
 }
 
-impl ImageSource {
-    fn new(directory: &str) -> Result<Self, Box<dyn Error>> {
-        let paths: Vec<String> = glob(&format!("{}/*.png", directory))?
-            .filter_map(Result::ok)
-            .map(|p| p.display().to_string())
-            .collect();
-        Ok(Self { paths, index: 0 })
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl InferenceProfile {
+    /// Layers sorted by time spent, slowest first.
+    fn slowest_layers(&self, count: usize) -> Vec<(String, f64)> {
+        let mut layers = self.layer_times.clone();
+        layers.sort_by(|a, b| b.1.total_cmp(&a.1));
+        layers.truncate(count);
+        layers
     }
 }
 
-impl DataSource for ImageSource {
-    type Item = DynamicImage;
-    type Error = ProcessingError;
-
-    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
-        if self.index >= self.paths.len() {
-            return None;
-        }
-        let path = &self.paths[self.index];
-        self.index += 1;
-        match image::open(path) {
-            Ok(img) => Some(Ok((path.clone(), img))),
-            Err(e) => Some(Err(ProcessingError(e.to_string()))),
-        }
+#[cfg(feature = "inference")]
+// With the default 1/255 scale and zero mean, values should lie in [0, 1]; a max near 255
+// means the scale was not applied, a negative min means the mean was subtracted twice
+fn log_blob_stats(blob: &core::Mat) {
+    match blob_stats(blob) {
+        Ok((mean, stddev, min, max)) => tracing::debug!(mean, stddev, min, max, "Blob statistics"),
+        Err(e) => tracing::debug!(error = %e, "Failed to compute blob statistics"),
     }
 }
 
-#[derive(Debug)]
-enum SystemMessage {
-    ProcessingResult(Result<(String, Vec<(u32, f32, f32, f32, f32)>), ProcessingError>),
-    Completed,
+#[cfg(feature = "inference")]
+fn blob_stats(blob: &core::Mat) -> Result<(f64, f64, f64, f64), ProcessingError> {
+    // The blob is 4D (NCHW); the statistics functions want a plain 2D matrix
+    let flat = blob.reshape(1, 1)?;
+    let mut mean = core::Vector::<f64>::new();
+    let mut stddev = core::Vector::<f64>::new();
+    core::mean_std_dev(&flat, &mut mean, &mut stddev, &core::Mat::default())?;
+    let (mut min, mut max) = (0.0, 0.0);
+    core::min_max_loc(&flat, Some(&mut min), Some(&mut max), None, None, &core::Mat::default())?;
+    Ok((mean.get(0)?, stddev.get(0)?, min, max))
 }
 
-struct ProcessingSystem<T, D>
-where
-    T: Task<Input = DynamicImage, Output = Vec<(u32, f32, f32, f32, f32)>, Error = ProcessingError> + Clone,
-    D: DataSource<Item = DynamicImage, Error = ProcessingError> + Clone,
-{
-    task: T,
-    data_source: D,
+#[cfg(feature = "inference")]
+// Maps blob-normalized coordinates back to original pixels: x * scale - offset
+#[derive(Debug, Clone, Copy)]
+struct BoxMapping {
+    image_size: (f32, f32),
+    scale: (f32, f32),
+    offset: (f32, f32),
 }
 
-impl<T, D> ProcessingSystem<T, D>
-where
-    T: Task<Input = DynamicImage, Output = Vec<(u32, f32, f32, f32, f32)>, Error = ProcessingError> + Clone,
-    D: DataSource<Item = DynamicImage, Error = ProcessingError> + Clone,
-{
-    fn new(task: T, data_source: D) -> Self {
-        Self { task, data_source }
+#[cfg(feature = "inference")]
+// Batched outputs may come back as [batch, rows, attributes]; view them as one 2D table
+fn flatten_output(output: &core::Mat) -> Result<core::Mat, ProcessingError> {
+    if output.dims() <= 2 {
+        return Ok(output.try_clone()?);
     }
+    let attributes = output.mat_size()[output.dims() as usize - 1];
+    Ok(output.reshape(1, output.total() as i32 / attributes)?)
+}
 
-    async fn run(&mut self, num_workers: usize) {
-        let (tx, mut rx) = mpsc::channel(100);
+#[cfg(feature = "inference")]
+// Intersects `rect` with a `width` x `height` image; None if nothing of it is left
+fn clamp_rect(rect: core::Rect, width: i32, height: i32) -> Option<core::Rect> {
+    let left = rect.x.clamp(0, width);
+    let top = rect.y.clamp(0, height);
+    let right = rect.x.saturating_add(rect.width).clamp(0, width);
+    let bottom = rect.y.saturating_add(rect.height).clamp(0, height);
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some(core::Rect::new(left, top, right - left, bottom - top))
+}
 
-        for _ in 0..num_workers {
-            let tx = tx.clone();  // Removed unnecessary mut
-            let task = self.task.clone();
-            let mut data_source = self.data_source.clone();
+#[cfg(feature = "inference")]
+// Resizes to fit `target_size` and pads the remainder with gray (114, as in the YOLO reference
+// implementations); returns the scale factor and the left/top padding
+fn letterbox(img: &core::Mat, target_size: core::Size) -> Result<(core::Mat, f32, (i32, i32)), ProcessingError> {
+    let scale = (target_size.width as f32 / img.cols() as f32).min(target_size.height as f32 / img.rows() as f32);
+    let resized_width = ((img.cols() as f32 * scale).round() as i32).clamp(1, target_size.width);
+    let resized_height = ((img.rows() as f32 * scale).round() as i32).clamp(1, target_size.height);
 
-            tokio::spawn(async move {
-                while let Some(data) = data_source.get_data() {
-                    match data {
-                        Ok((path, img)) => {
-                            let result = task.process(img).map(|annotations| (path, annotations));
-                            let _ = tx.send(SystemMessage::ProcessingResult(result)).await;
-                        }
-                        Err(e) => {
-                            let _ = tx.send(SystemMessage::ProcessingResult(Err(e))).await;
-                        }
-                    }
-                }
-                let _ = tx.send(SystemMessage::Completed).await;
-            });
-        }
+    let mut resized = core::Mat::default();
+    imgproc::resize(
+        img,
+        &mut resized,
+        core::Size::new(resized_width, resized_height),
+        0.0,
+        0.0,
+        imgproc::INTER_LINEAR,
+    )?;
 
-        let mut completed = 0;
-        while let Some(msg) = rx.recv().await {
-            match msg {
-                SystemMessage::ProcessingResult(Ok((path, annotations))) => {
-                    save_labels(&path, annotations).expect("Failed to save labels");
-                    println!("Annotations saved for {}", path);
-                }
-                SystemMessage::ProcessingResult(Err(e)) => {
-                    println!("Error: {}", e);
-                }
-                SystemMessage::Completed => {
-                    completed += 1;
-                    if completed == num_workers {
-                        break;
-                    }
-                }
+    let pad_x = (target_size.width - resized_width) / 2;
+    let pad_y = (target_size.height - resized_height) / 2;
+    let mut padded = core::Mat::default();
+    core::copy_make_border(
+        &resized,
+        &mut padded,
+        pad_y,
+        target_size.height - resized_height - pad_y,
+        pad_x,
+        target_size.width - resized_width - pad_x,
+        core::BORDER_CONSTANT,
+        core::Scalar::all(114.0),
+    )?;
+    Ok((padded, scale, (pad_x, pad_y)))
+}
+
+#[cfg(feature = "inference")]
+impl Task for ObjectDetectionTask {
+    type Input = DynamicImage;
+    type Output = Vec<Detection>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.detect_objects(&input)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn process_batch(&self, inputs: Vec<Self::Input>) -> Vec<Result<Self::Output, Self::Error>> {
+        match self.detect_objects_batch(&inputs) {
+            Ok(outputs) => outputs.into_iter().map(Ok).collect(),
+            // Retrying one by one keeps a single undecodable image from failing its whole batch
+            Err(e) => {
+                tracing::warn!(error = %e, images = inputs.len(), "Batched inference failed, retrying images one by one");
+                inputs.iter().map(|input| self.detect_objects(input)).collect()
             }
         }
     }
 }
 
-fn save_labels(image_path: &str, labels: Vec<(u32, f32, f32, f32, f32)>) -> Result<(), Box<dyn Error>> {
-    let filename = Path::new(image_path)
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let output_dir = Path::new("./output/labels");
-    fs::create_dir_all(output_dir)?;
-    let output_path = output_dir.join(format!("{}.txt", filename));
-    let mut file = File::create(output_path)?;
+#[cfg(feature = "inference")]
+#[derive(Clone)]
+struct ObjectDetectionTaskPool {
+    replicas: Arc<Vec<ObjectDetectionTask>>,
+    idle: Arc<Mutex<Vec<usize>>>,
+    // Signalled whenever a replica is returned, so a checkout waits instead of spinning on busy networks
+    returned: Arc<std::sync::Condvar>,
+}
 
-    for (class_id, x_center, y_center, width, height) in labels {
-        writeln!(file, "{} {:.6} {:.6} {:.6} {:.6}", class_id, x_center, y_center, width, height)?;
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl ObjectDetectionTaskPool {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        cfg_path: &str,
+        weights_path: &str,
+        width: i32,
+        height: i32,
+        blob_config: BlobConfig,
+        confidence_threshold: f32,
+        nms_threshold: f32,
+        pool_size: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let replicas = (0..pool_size.max(1))
+            .map(|_| {
+                ObjectDetectionTask::new(
+                    cfg_path,
+                    weights_path,
+                    width,
+                    height,
+                    blob_config,
+                    confidence_threshold,
+                    nms_threshold,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_tasks(replicas))
     }
 
-    Ok(())
+    // Each task must own its own network for the replicas to run in parallel
+    fn from_tasks(replicas: Vec<ObjectDetectionTask>) -> Self {
+        let count = replicas.len();
+        Self {
+            replicas: Arc::new(replicas),
+            idle: Arc::new(Mutex::new((0..count).collect())),
+            returned: Arc::new(std::sync::Condvar::new()),
+        }
+    }
+
+    // Blocks the calling thread, which dispatch_batch keeps off the runtime workers
+    fn checkout(&self) -> Result<usize, ProcessingError> {
+        let idle = self.idle.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        let mut idle = self
+            .returned
+            .wait_while(idle, |idle| idle.is_empty())
+            .map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        idle.pop()
+            .ok_or_else(|| ProcessingError::Lock("no idle replica after wake-up".to_string()))
+    }
+
+    fn check_in(&self, index: usize) -> Result<(), ProcessingError> {
+        self.idle.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?.push(index);
+        self.returned.notify_one();
+        Ok(())
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let task = ObjectDetectionTask::new("yolov3.cfg", "yolov3.weights", 416, 416)?;
-    let data_source = ImageSource::new("./screenshots")?;
-    let mut system = ProcessingSystem::new(task, data_source);
+#[cfg(feature = "inference")]
+impl Task for ObjectDetectionTaskPool {
+    type Input = DynamicImage;
+    type Output = Vec<Detection>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let index = self.checkout()?;
+        let result = self.replicas[index].detect_objects(&input);
+        self.check_in(index)?;
+        result
+    }
+}
 
-    println!("Starting automated annotation system...");
-    system.run(4).await;
+#[cfg(feature = "inference")]
+/// Runs detection on overlapping network-sized tiles, so small sprites in high-DPI screenshots
+/// are not lost when the whole frame is downscaled to the network input.
+#[derive(Clone)]
+struct TiledDetectionTask {
+    inner: ObjectDetectionTask,
+    overlap: f32,
+}
 
-    Ok(())
-}
\ No newline at end of file
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl TiledDetectionTask {
+    /// `overlap` is the fraction of each tile shared with its neighbour, in `[0.0, 0.9]`.
+    fn new(inner: ObjectDetectionTask, overlap: f32) -> Self {
+        Self {
+            inner,
+            overlap: overlap.clamp(0.0, 0.9),
+        }
+    }
+
+    fn detect_tiled(&self, input: &DynamicImage) -> Result<Vec<Detection>, ProcessingError> {
+        let (img_width, img_height) = input.dimensions();
+        let tile_width = (self.inner.width.max(1) as u32).min(img_width);
+        let tile_height = (self.inner.height.max(1) as u32).min(img_height);
+        let stride_x = ((tile_width as f32 * (1.0 - self.overlap)) as u32).max(1);
+        let stride_y = ((tile_height as f32 * (1.0 - self.overlap)) as u32).max(1);
+
+        let mut candidates = Vec::new();
+        for y in tile_origins(img_height, tile_height, stride_y) {
+            for x in tile_origins(img_width, tile_width, stride_x) {
+                let tile = input.crop_imm(x, y, tile_width, tile_height);
+                // Tile detections are normalized to the tile; project them onto the full image
+                for detection in self.inner.detect_objects(&tile)? {
+                    candidates.push(Detection {
+                        x_center: (x as f32 + detection.x_center * tile_width as f32) / img_width as f32,
+                        y_center: (y as f32 + detection.y_center * tile_height as f32) / img_height as f32,
+                        width: detection.width * tile_width as f32 / img_width as f32,
+                        height: detection.height * tile_height as f32 / img_height as f32,
+                        ..detection
+                    });
+                }
+            }
+        }
+        Ok(suppress_duplicates(candidates, self.inner.nms_threshold))
+    }
+}
+
+#[cfg(feature = "inference")]
+// The last tile is aligned to the far edge, so the whole axis is covered without padding
+fn tile_origins(length: u32, tile: u32, stride: u32) -> Vec<u32> {
+    if length <= tile {
+        return vec![0];
+    }
+    let mut origins: Vec<u32> = (0..length - tile).step_by(stride as usize).collect();
+    origins.push(length - tile);
+    origins
+}
+
+#[cfg(feature = "inference")]
+// Greedy per-class NMS over normalized boxes; IoU is unaffected by the non-uniform scaling
+fn suppress_duplicates(mut candidates: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    let mut kept: Vec<Detection> = Vec::new();
+    for candidate in candidates {
+        let duplicate = kept
+            .iter()
+            .any(|k| k.class_id == candidate.class_id && metrics::iou(k, &candidate) > iou_threshold);
+        if !duplicate {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+#[cfg(feature = "inference")]
+impl Task for TiledDetectionTask {
+    type Input = DynamicImage;
+    type Output = Vec<Detection>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.detect_tiled(&input)
+    }
+}
+
+#[cfg(feature = "inference")]
+/// Runs detection on resized copies of each image, so small sprites near the top of the
+/// playfield are seen at several sizes. Scaled copies larger than the network input are tiled.
+#[derive(Clone)]
+struct PyramidDetectionTask {
+    tiles: TiledDetectionTask,
+    scales: Vec<f32>,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl PyramidDetectionTask {
+    /// Non-positive scales are dropped; with none left the image is only processed as is.
+    fn new(inner: ObjectDetectionTask, scales: Vec<f32>) -> Self {
+        let mut scales: Vec<f32> = scales.into_iter().filter(|scale| *scale > 0.0).collect();
+        if scales.is_empty() {
+            scales.push(1.0);
+        }
+        Self {
+            tiles: TiledDetectionTask::new(inner, 0.2),
+            scales,
+        }
+    }
+
+    fn detect_pyramid(&self, input: &DynamicImage) -> Result<Vec<Detection>, ProcessingError> {
+        let (img_width, img_height) = input.dimensions();
+        let mut candidates = Vec::new();
+        for &scale in &self.scales {
+            let width = ((img_width as f32 * scale).round() as u32).max(1);
+            let height = ((img_height as f32 * scale).round() as u32).max(1);
+            // Normalized coordinates are the same at every scale, so no projection is needed
+            let detections = if (width, height) == (img_width, img_height) {
+                self.tiles.detect_tiled(input)?
+            } else {
+                let scaled = input.resize_exact(width, height, image::imageops::FilterType::Triangle);
+                self.tiles.detect_tiled(&scaled)?
+            };
+            candidates.extend(detections);
+        }
+        Ok(suppress_duplicates(candidates, self.tiles.inner.nms_threshold))
+    }
+}
+
+#[cfg(feature = "inference")]
+impl Task for PyramidDetectionTask {
+    type Input = DynamicImage;
+    type Output = Vec<Detection>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.detect_pyramid(&input)
+    }
+}
+
+#[cfg(feature = "inference")]
+/// How `RegionProposalTask` finds the parts of a frame worth running the detector on.
+#[derive(Debug, Clone, Copy)]
+enum ProposalMethod {
+    /// SLICO superpixels whose mean colour is further than `contrast` (BGR distance) from the
+    /// frame's mean colour; suits the flat sea and sky backgrounds of the playfield.
+    Slic { region_size: i32, contrast: f64 },
+    /// Dilated Canny edges, with `low` and `high` as the hysteresis thresholds.
+    Edges { low: f64, high: f64 },
+}
+
+#[cfg(feature = "inference")]
+impl ProposalMethod {
+    fn foreground_mask(&self, frame: &core::Mat) -> Result<core::Mat, ProcessingError> {
+        match *self {
+            ProposalMethod::Slic { region_size, contrast } => slic_mask(frame, region_size, contrast),
+            ProposalMethod::Edges { low, high } => {
+                let mut gray = core::Mat::default();
+                imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+                let mut edges = core::Mat::default();
+                imgproc::canny(&gray, &mut edges, low, high, 3, false)?;
+                // Joins the outline of each sprite into one blob
+                let kernel = imgproc::get_structuring_element(
+                    imgproc::MORPH_RECT,
+                    core::Size::new(5, 5),
+                    core::Point::new(-1, -1),
+                )?;
+                let mut mask = core::Mat::default();
+                imgproc::dilate(
+                    &edges,
+                    &mut mask,
+                    &kernel,
+                    core::Point::new(-1, -1),
+                    1,
+                    core::BORDER_CONSTANT,
+                    imgproc::morphology_default_border_value()?,
+                )?;
+                Ok(mask)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "inference")]
+fn slic_mask(frame: &core::Mat, region_size: i32, contrast: f64) -> Result<core::Mat, ProcessingError> {
+    use ximgproc::{SuperpixelSLIC, SuperpixelSLICConst};
+
+    let mut slic = ximgproc::create_superpixel_slic(frame, ximgproc::SLICO, region_size.max(2), 10.0)?;
+    slic.iterate(10)?;
+    let mut labels = core::Mat::default();
+    slic.get_labels(&mut labels)?;
+    let count = slic.get_number_of_superpixels()?.max(0) as usize;
+
+    let label_data = labels.data_typed::<i32>()?;
+    let pixels = frame.data_typed::<core::Vec3b>()?;
+    let mut sums = vec![([0.0f64; 3], 0usize); count];
+    let mut total = [0.0f64; 3];
+    for (&label, pixel) in label_data.iter().zip(pixels) {
+        let (sum, n) = &mut sums[label as usize];
+        for channel in 0..3 {
+            sum[channel] += pixel[channel] as f64;
+            total[channel] += pixel[channel] as f64;
+        }
+        *n += 1;
+    }
+    let pixel_count = pixels.len().max(1) as f64;
+    let frame_mean = total.map(|channel| channel / pixel_count);
+    let distinct: Vec<bool> = sums
+        .iter()
+        .map(|(sum, n)| {
+            let n = (*n).max(1) as f64;
+            let distance = (0..3).map(|c| (sum[c] / n - frame_mean[c]).powi(2)).sum::<f64>().sqrt();
+            distance > contrast
+        })
+        .collect();
+
+    let mut mask = core::Mat::new_rows_cols_with_default(frame.rows(), frame.cols(), core::CV_8UC1, core::Scalar::all(0.0))?;
+    for (value, &label) in mask.data_typed_mut::<u8>()?.iter_mut().zip(label_data) {
+        if distinct[label as usize] {
+            *value = 255;
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(feature = "inference")]
+// Bounding rects of the blobs in a binary mask, ignoring specks of a few pixels
+fn mask_regions(mask: &core::Mat) -> Result<Vec<core::Rect>, ProcessingError> {
+    const MIN_REGION_AREA: i32 = 16;
+    let mut contours = core::Vector::<core::Vector<core::Point>>::new();
+    imgproc::find_contours(
+        mask,
+        &mut contours,
+        imgproc::RETR_EXTERNAL,
+        imgproc::CHAIN_APPROX_SIMPLE,
+        core::Point::default(),
+    )?;
+    let mut regions = Vec::with_capacity(contours.len());
+    for contour in contours.iter() {
+        let rect = imgproc::bounding_rect(&contour)?;
+        if rect.area() >= MIN_REGION_AREA {
+            regions.push(rect);
+        }
+    }
+    Ok(regions)
+}
+
+#[cfg(feature = "inference")]
+/// Runs the detector only on crops around proposed regions rather than the whole frame, which
+/// is faster when most of the screen is empty background. Frames without proposals yield no
+/// detections.
+#[derive(Clone)]
+struct RegionProposalTask {
+    inner: ObjectDetectionTask,
+    method: ProposalMethod,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl RegionProposalTask {
+    fn new(inner: ObjectDetectionTask, method: ProposalMethod) -> Self {
+        Self { inner, method }
+    }
+
+    fn detect_regions(&self, input: &DynamicImage) -> Result<Vec<Detection>, ProcessingError> {
+        let mask = self.method.foreground_mask(&image_to_mat(input)?)?;
+        let candidates = self.inner.detect_in_regions(input, &mask_regions(&mask)?)?;
+        // Neighbouring proposals often share a crop's worth of pixels
+        Ok(suppress_duplicates(candidates, self.inner.nms_threshold))
+    }
+}
+
+#[cfg(feature = "inference")]
+impl Task for RegionProposalTask {
+    type Input = DynamicImage;
+    type Output = Vec<Detection>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.detect_regions(&input)
+    }
+}
+
+#[cfg(feature = "inference")]
+/// Refines each detected box into a pixel mask with GrabCut. Masks are full-frame `Luma8`
+/// images with the object at 255, so they line up with the source screenshot.
+#[derive(Clone)]
+struct SegmentationTask {
+    inner: ObjectDetectionTask,
+    iterations: i32,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl SegmentationTask {
+    fn new(inner: ObjectDetectionTask) -> Self {
+        Self { inner, iterations: 5 }
+    }
+
+    fn with_iterations(mut self, iterations: i32) -> Self {
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    fn segment(&self, input: &DynamicImage) -> Result<Vec<(Detection, DynamicImage)>, ProcessingError> {
+        let detections = self.inner.detect_objects(input)?;
+        if detections.is_empty() {
+            return Ok(Vec::new());
+        }
+        let frame = image_to_mat(input)?;
+        let (width, height) = (frame.cols(), frame.rows());
+
+        let mut masks = Vec::with_capacity(detections.len());
+        for detection in detections {
+            let rect = core::Rect::new(
+                ((detection.x_center - detection.width / 2.0) * width as f32) as i32,
+                ((detection.y_center - detection.height / 2.0) * height as f32) as i32,
+                (detection.width * width as f32).round() as i32,
+                (detection.height * height as f32).round() as i32,
+            );
+            let Some(rect) = clamp_rect(rect, width, height) else { continue };
+            let mask = match self.grab_cut(&frame, rect) {
+                Ok(mask) => mask,
+                Err(e) => {
+                    // GrabCut rejects boxes too small to model; fall back to the filled box
+                    tracing::debug!(error = %e, "GrabCut failed, using the bounding box as mask");
+                    box_mask(width as u32, height as u32, rect)
+                }
+            };
+            masks.push((detection, DynamicImage::ImageLuma8(mask)));
+        }
+        Ok(masks)
+    }
+
+    fn grab_cut(&self, frame: &core::Mat, rect: core::Rect) -> Result<image::GrayImage, ProcessingError> {
+        let mut mask = core::Mat::default();
+        let mut background_model = core::Mat::default();
+        let mut foreground_model = core::Mat::default();
+        imgproc::grab_cut(
+            frame,
+            &mut mask,
+            rect,
+            &mut background_model,
+            &mut foreground_model,
+            self.iterations,
+            imgproc::GC_INIT_WITH_RECT,
+        )?;
+        // GC_FGD and GC_PR_FGD are the odd labels
+        let pixels = mask.data_bytes()?.iter().map(|label| if label & 1 == 1 { 255 } else { 0 }).collect();
+        image::GrayImage::from_raw(frame.cols() as u32, frame.rows() as u32, pixels).ok_or_else(|| {
+            ProcessingError::InvalidDetection("GrabCut mask does not match the frame size".to_string())
+        })
+    }
+
+    /// Writes each mask as `{stem}_mask_{class_id}_{idx}.png` under `output_dir`, where `idx`
+    /// counts detections within the image.
+    fn save_masks(
+        &self,
+        output_dir: &Path,
+        image_path: &str,
+        masks: &[(Detection, DynamicImage)],
+    ) -> Result<Vec<PathBuf>, ProcessingError> {
+        let stem = Path::new(image_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| invalid_path(image_path))?;
+        fs::create_dir_all(output_dir)?;
+        let mut written = Vec::with_capacity(masks.len());
+        for (idx, (detection, mask)) in masks.iter().enumerate() {
+            let path = output_dir.join(format!("{}_mask_{}_{}.png", stem, detection.class_id, idx));
+            mask.save(&path)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(feature = "inference")]
+fn box_mask(width: u32, height: u32, rect: core::Rect) -> image::GrayImage {
+    image::GrayImage::from_fn(width, height, |x, y| {
+        let inside = rect.contains(core::Point::new(x as i32, y as i32));
+        image::Luma([if inside { 255 } else { 0 }])
+    })
+}
+
+#[cfg(feature = "inference")]
+impl Task for SegmentationTask {
+    type Input = DynamicImage;
+    type Output = Vec<(Detection, DynamicImage)>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.segment(&input)
+    }
+}
+
+#[cfg(feature = "inference")]
+/// Runs several independently trained models on each image and fuses their boxes.
+#[derive(Clone)]
+struct EnsembleTask {
+    models: Vec<ObjectDetectionTask>,
+    iou_threshold: f32,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl EnsembleTask {
+    fn new(models: Vec<ObjectDetectionTask>, iou_threshold: f32) -> Self {
+        Self { models, iou_threshold }
+    }
+}
+
+#[cfg(feature = "inference")]
+impl Task for EnsembleTask {
+    type Input = DynamicImage;
+    type Output = Vec<Detection>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let outputs = self
+            .models
+            .iter()
+            .map(|model| model.detect_objects(&input))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(merge_detections(&outputs, self.iou_threshold))
+    }
+}
+
+#[cfg(feature = "inference")]
+// Weighted box fusion: unlike NMS, overlapping boxes from different models are averaged
+// (weighted by confidence) rather than discarded, and boxes only some models found are
+// down-weighted by the fraction of models that agreed
+fn merge_detections(sources: &[Vec<Detection>], iou_threshold: f32) -> Vec<Detection> {
+    let mut candidates: Vec<Detection> = sources.iter().flatten().copied().collect();
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let mut clusters: Vec<(Detection, Vec<Detection>)> = Vec::new();
+    for candidate in candidates {
+        let matched = clusters
+            .iter_mut()
+            .filter(|(fused, _)| fused.class_id == candidate.class_id)
+            .map(|cluster| (metrics::iou(&cluster.0, &candidate), cluster))
+            .filter(|(iou, _)| *iou > iou_threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        match matched {
+            Some((_, (fused, members))) => {
+                members.push(candidate);
+                *fused = fuse_boxes(members);
+            }
+            None => clusters.push((candidate, vec![candidate])),
+        }
+    }
+
+    let model_count = sources.len().max(1) as f32;
+    clusters
+        .into_iter()
+        .map(|(fused, members)| Detection {
+            confidence: fused.confidence * members.len().min(sources.len()) as f32 / model_count,
+            ..fused
+        })
+        .collect()
+}
+
+#[cfg(feature = "inference")]
+fn fuse_boxes(members: &[Detection]) -> Detection {
+    let total: f32 = members.iter().map(|d| d.confidence).sum();
+    let mut corners = [0.0f32; 4];
+    for member in members {
+        let (x1, y1, x2, y2) = augment::to_corners(member, 1.0, 1.0);
+        for (sum, value) in corners.iter_mut().zip([x1, y1, x2, y2]) {
+            *sum += value * member.confidence / total;
+        }
+    }
+    let [x1, y1, x2, y2] = corners;
+    Detection {
+        class_id: members[0].class_id,
+        confidence: total / members.len() as f32,
+        x_center: (x1 + x2) / 2.0,
+        y_center: (y1 + y2) / 2.0,
+        width: x2 - x1,
+        height: y2 - y1,
+    }
+}
+
+#[derive(Clone)]
+struct RetryTask<T: Task> {
+    inner: T,
+    max_retries: u32,
+    base_delay_ms: u64,
+}
+
+#[allow(dead_code)]
+impl<T: Task> RetryTask<T> {
+    fn new(inner: T, max_retries: u32, base_delay_ms: u64) -> Self {
+        Self {
+            inner,
+            max_retries,
+            base_delay_ms,
+        }
+    }
+}
+
+impl<T: Task> Task for RetryTask<T>
+where
+    T::Input: Clone,
+{
+    type Input = T::Input;
+    type Output = T::Output;
+    type Error = T::Error;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.process(input.clone()) {
+                Ok(output) => return Ok(output),
+                Err(e) if attempt >= self.max_retries => return Err(e),
+                Err(_) => {
+                    let delay = Duration::from_millis(self.base_delay_ms.saturating_mul(1 << attempt.min(16)));
+                    // dispatch_batch runs tasks on the blocking pool, so this only parks that thread
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Feeds the output of `first` into `second`; built with `chain`.
+#[derive(Clone)]
+struct ChainedTask<A, B> {
+    first: A,
+    second: B,
+}
+
+/// Composes two tasks, e.g. a preprocessing step with a detector, into one `Task` that
+/// `ProcessingSystem` can run. Errors from `first` are converted into `second`'s error type.
+#[allow(dead_code)]
+fn chain<A, B>(first: A, second: B) -> ChainedTask<A, B>
+where
+    A: Task,
+    B: Task<Input = A::Output>,
+    B::Error: From<A::Error>,
+{
+    ChainedTask { first, second }
+}
+
+impl<A, B> Task for ChainedTask<A, B>
+where
+    A: Task,
+    B: Task<Input = A::Output>,
+    B::Error: From<A::Error>,
+{
+    type Input = A::Input;
+    type Output = B::Output;
+    type Error = B::Error;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let intermediate = self.first.process(input)?;
+        self.second.process(intermediate)
+    }
+}
+
+/// Transforms the output of `inner` with a pure function, e.g. to relabel or rescale boxes.
+struct MapTask<T: Task, U, F: Fn(T::Output) -> U> {
+    inner: T,
+    f: F,
+    _output: PhantomData<fn() -> U>,
+}
+
+#[allow(dead_code)]
+impl<T: Task, U, F: Fn(T::Output) -> U> MapTask<T, U, F> {
+    fn new(inner: T, f: F) -> Self {
+        Self {
+            inner,
+            f,
+            _output: PhantomData,
+        }
+    }
+}
+
+// Derived Clone would needlessly require U: Clone
+impl<T: Task + Clone, U, F: Fn(T::Output) -> U + Clone> Clone for MapTask<T, U, F> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone(), self.f.clone())
+    }
+}
+
+impl<T, U, F> Task for MapTask<T, U, F>
+where
+    T: Task,
+    U: 'static,
+    F: Fn(T::Output) -> U + Send + Sync + 'static,
+{
+    type Input = T::Input;
+    type Output = U;
+    type Error = T::Error;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.inner.process(input).map(&self.f)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.inner.batch_size()
+    }
+
+    fn process_batch(&self, inputs: Vec<Self::Input>) -> Vec<Result<Self::Output, Self::Error>> {
+        self.inner
+            .process_batch(inputs)
+            .into_iter()
+            .map(|result| result.map(&self.f))
+            .collect()
+    }
+}
+
+/// Keeps only the output elements `predicate` accepts, e.g. one class or a confidence floor.
+struct FilterTask<T: Task<Output = Vec<V>>, V, F: Fn(&V) -> bool> {
+    inner: T,
+    predicate: F,
+    _element: PhantomData<fn() -> V>,
+}
+
+#[allow(dead_code)]
+impl<T: Task<Output = Vec<V>>, V, F: Fn(&V) -> bool> FilterTask<T, V, F> {
+    fn new(inner: T, predicate: F) -> Self {
+        Self {
+            inner,
+            predicate,
+            _element: PhantomData,
+        }
+    }
+
+    fn retain(&self, mut output: Vec<V>) -> Vec<V> {
+        output.retain(|item| (self.predicate)(item));
+        output
+    }
+}
+
+impl<T: Task<Output = Vec<V>> + Clone, V, F: Fn(&V) -> bool + Clone> Clone for FilterTask<T, V, F> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone(), self.predicate.clone())
+    }
+}
+
+impl<T, V, F> Task for FilterTask<T, V, F>
+where
+    T: Task<Output = Vec<V>>,
+    V: 'static,
+    F: Fn(&V) -> bool + Send + Sync + 'static,
+{
+    type Input = T::Input;
+    type Output = Vec<V>;
+    type Error = T::Error;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.inner.process(input).map(|output| self.retain(output))
+    }
+
+    fn batch_size(&self) -> usize {
+        self.inner.batch_size()
+    }
+
+    fn process_batch(&self, inputs: Vec<Self::Input>) -> Vec<Result<Self::Output, Self::Error>> {
+        self.inner
+            .process_batch(inputs)
+            .into_iter()
+            .map(|result| result.map(|output| self.retain(output)))
+            .collect()
+    }
+}
+
+/// Expands each output of `inner` into any number of items, e.g. one labeled screenshot into
+/// several augmented `(DynamicImage, Vec<Detection>)` variants that are saved separately.
+struct FlatMapTask<T: Task, U, F: Fn(T::Output) -> Vec<U>> {
+    inner: T,
+    f: F,
+    _output: PhantomData<fn() -> U>,
+}
+
+#[allow(dead_code)]
+impl<T: Task, U, F: Fn(T::Output) -> Vec<U>> FlatMapTask<T, U, F> {
+    fn new(inner: T, f: F) -> Self {
+        Self {
+            inner,
+            f,
+            _output: PhantomData,
+        }
+    }
+}
+
+impl<T: Task + Clone, U, F: Fn(T::Output) -> Vec<U> + Clone> Clone for FlatMapTask<T, U, F> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone(), self.f.clone())
+    }
+}
+
+impl<T, U, F> Task for FlatMapTask<T, U, F>
+where
+    T: Task,
+    U: 'static,
+    F: Fn(T::Output) -> Vec<U> + Send + Sync + 'static,
+{
+    type Input = T::Input;
+    type Output = Vec<U>;
+    type Error = T::Error;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.inner.process(input).map(&self.f)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.inner.batch_size()
+    }
+
+    fn process_batch(&self, inputs: Vec<Self::Input>) -> Vec<Result<Self::Output, Self::Error>> {
+        self.inner
+            .process_batch(inputs)
+            .into_iter()
+            .map(|result| result.map(&self.f))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ValidationIssue {
+    MissingLabelFile { path: String },
+    TruncatedLine { line: usize, content: String },
+    /// More than the 5 YOLO fields plus an optional confidence.
+    ExtraFields { line: usize, fields: usize },
+    CoordinateOutOfRange { line: usize, value: f32 },
+    UnknownClass { line: usize, class_id: u32 },
+    DuplicateBox { first_line: usize, second_line: usize, iou: f32 },
+}
+
+#[derive(Clone)]
+struct LabelValidationTask {
+    label_dir: PathBuf,
+    num_classes: u32,
+    duplicate_iou_threshold: f32,
+}
+
+impl LabelValidationTask {
+    fn new(label_dir: &str, num_classes: u32, duplicate_iou_threshold: f32) -> Self {
+        Self {
+            label_dir: PathBuf::from(label_dir),
+            num_classes,
+            duplicate_iou_threshold,
+        }
+    }
+
+    fn validate(&self, image_path: &str) -> Result<Vec<ValidationIssue>, ProcessingError> {
+        let stem = Path::new(image_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| invalid_path(image_path))?;
+        let label_path = self.label_dir.join(format!("{}.txt", stem));
+        if !label_path.exists() {
+            return Ok(vec![ValidationIssue::MissingLabelFile {
+                path: label_path.display().to_string(),
+            }]);
+        }
+        let contents = fs::read_to_string(&label_path)?;
+
+        let mut issues = Vec::new();
+        let mut boxes: Vec<(usize, Detection)> = Vec::new();
+
+        for (index, raw) in contents.lines().enumerate() {
+            let line = index + 1;
+            if raw.trim().is_empty() {
+                continue;
+            }
+            // Same limit as lint_labels and read_yolo_labels; fewer fields are caught as truncated below
+            let fields = raw.split_whitespace().count();
+            if fields > 6 {
+                issues.push(ValidationIssue::ExtraFields { line, fields });
+                continue;
+            }
+
+            let detection = match parse_yolo_line(raw) {
+                Some(detection) => detection,
+                None => {
+                    issues.push(ValidationIssue::TruncatedLine {
+                        line,
+                        content: raw.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            if detection.class_id >= self.num_classes {
+                issues.push(ValidationIssue::UnknownClass {
+                    line,
+                    class_id: detection.class_id,
+                });
+            }
+            for value in [detection.x_center, detection.y_center, detection.width, detection.height] {
+                if !(0.0..=1.0).contains(&value) {
+                    issues.push(ValidationIssue::CoordinateOutOfRange { line, value });
+                }
+            }
+            for (first_line, other) in &boxes {
+                let iou = metrics::iou(other, &detection);
+                if other.class_id == detection.class_id && iou > self.duplicate_iou_threshold {
+                    issues.push(ValidationIssue::DuplicateBox {
+                        first_line: *first_line,
+                        second_line: line,
+                        iou,
+                    });
+                }
+            }
+            boxes.push((line, detection));
+        }
+
+        Ok(issues)
+    }
+}
+
+impl Task for LabelValidationTask {
+    type Input = (DynamicImage, String);
+    type Output = Vec<ValidationIssue>;
+    type Error = ProcessingError;
+
+    fn process(&self, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.validate(&input.1)
+    }
+}
+
+/// A problem found by `lint_labels`; lines are 1-based.
+#[derive(Debug, Clone, PartialEq)]
+enum LintError {
+    ClassOutOfRange { path: PathBuf, line: usize, class_id: u32 },
+    CoordinateOutOfRange { path: PathBuf, line: usize, value: f32 },
+    ZeroArea { path: PathBuf, line: usize },
+    WrongFieldCount { path: PathBuf, line: usize, fields: usize },
+    Malformed { path: PathBuf, line: usize, content: String },
+    // Usually a detection run that found nothing or failed, rather than a truly empty image
+    EmptyFile { path: PathBuf },
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LintError::ClassOutOfRange { path, line, class_id } => {
+                write!(f, "{}:{}: class ID {} is out of range", path.display(), line, class_id)
+            }
+            LintError::CoordinateOutOfRange { path, line, value } => {
+                write!(f, "{}:{}: coordinate {} is outside [0, 1]", path.display(), line, value)
+            }
+            LintError::ZeroArea { path, line } => write!(f, "{}:{}: box has zero area", path.display(), line),
+            LintError::WrongFieldCount { path, line, fields } => {
+                write!(f, "{}:{}: expected 5 or 6 fields, found {}", path.display(), line, fields)
+            }
+            LintError::Malformed { path, line, content } => {
+                write!(f, "{}:{}: cannot parse {:?}", path.display(), line, content)
+            }
+            LintError::EmptyFile { path } => write!(f, "{}: no annotations", path.display()),
+        }
+    }
+}
+
+/// Checks every YOLO label file in `label_dir` without needing the images.
+fn lint_labels(label_dir: &Path, class_count: usize) -> Result<Vec<LintError>, Box<dyn Error>> {
+    let mut label_paths: Vec<PathBuf> = glob(&format!("{}/*.txt", label_dir.display()))?
+        .filter_map(Result::ok)
+        .collect();
+    label_paths.sort();
+
+    let mut errors = Vec::new();
+    for path in label_paths {
+        let contents = fs::read_to_string(&path)?;
+        if contents.trim().is_empty() {
+            errors.push(LintError::EmptyFile { path });
+            continue;
+        }
+        for (index, raw) in contents.lines().enumerate() {
+            let line = index + 1;
+            let fields = raw.split_whitespace().count();
+            if fields == 0 {
+                continue;
+            }
+            if !(5..=6).contains(&fields) {
+                errors.push(LintError::WrongFieldCount { path: path.clone(), line, fields });
+                continue;
+            }
+            let Some(detection) = parse_yolo_line(raw) else {
+                errors.push(LintError::Malformed {
+                    path: path.clone(),
+                    line,
+                    content: raw.to_string(),
+                });
+                continue;
+            };
+
+            if detection.class_id as usize >= class_count {
+                errors.push(LintError::ClassOutOfRange {
+                    path: path.clone(),
+                    line,
+                    class_id: detection.class_id,
+                });
+            }
+            for value in [detection.x_center, detection.y_center, detection.width, detection.height] {
+                if !(0.0..=1.0).contains(&value) {
+                    errors.push(LintError::CoordinateOutOfRange { path: path.clone(), line, value });
+                }
+            }
+            if detection.width <= 0.0 || detection.height <= 0.0 {
+                errors.push(LintError::ZeroArea { path: path.clone(), line });
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Reads a YOLO label file with 5 (`class cx cy w h`) or 6 (plus confidence) columns per line.
+/// Blank lines are skipped; any other malformed line fails the whole file.
+fn read_yolo_labels(path: &Path) -> Result<Vec<Detection>, ProcessingError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ProcessingError::Io(std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))))?;
+    let mut labels = Vec::new();
+    for (index, raw) in contents.lines().enumerate() {
+        let fields = raw.split_whitespace().count();
+        if fields == 0 {
+            continue;
+        }
+        match parse_yolo_line(raw).filter(|_| fields <= 6) {
+            Some(detection) => labels.push(detection),
+            None => {
+                return Err(ProcessingError::InvalidDetection(format!(
+                    "{}:{}: malformed label line {:?}",
+                    path.display(),
+                    index + 1,
+                    raw
+                )))
+            }
+        }
+    }
+    Ok(labels)
+}
+
+// Parses "class cx cy w h [confidence]"; None means the line is truncated or malformed
+fn parse_yolo_line(line: &str) -> Option<Detection> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 5 {
+        return None;
+    }
+    let class_id = fields[0].parse().ok()?;
+    let mut values = [0.0f32; 4];
+    for (value, field) in values.iter_mut().zip(&fields[1..5]) {
+        *value = field.parse().ok()?;
+    }
+    let confidence = match fields.get(5) {
+        Some(field) => field.parse().ok()?,
+        None => 1.0,
+    };
+    Some(Detection {
+        class_id,
+        confidence,
+        x_center: values[0],
+        y_center: values[1],
+        width: values[2],
+        height: values[3],
+    })
+}
+
+/// A fixed list of paths shared by every clone of a source, so that with several workers each
+/// path is claimed exactly once.
+#[derive(Clone)]
+struct PathQueue {
+    paths: Arc<Vec<String>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl PathQueue {
+    fn new(paths: Vec<String>) -> Self {
+        Self {
+            paths: Arc::new(paths),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn claim(&self) -> Option<String> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        self.paths.get(index).cloned()
+    }
+
+    /// A new queue over the unclaimed paths that `keep` accepts.
+    #[cfg_attr(not(feature = "inference"), allow(dead_code))]
+    fn filter(&self, mut keep: impl FnMut(&str) -> bool) -> Self {
+        let start = self.next.load(Ordering::Relaxed).min(self.paths.len());
+        Self::new(self.paths[start..].iter().filter(|path| keep(path.as_str())).cloned().collect())
+    }
+
+    fn remaining(&self) -> usize {
+        self.paths.len().saturating_sub(self.next.load(Ordering::Relaxed))
+    }
+
+    fn paths(&self) -> &[String] {
+        &self.paths
+    }
+}
+
+#[derive(Clone)]
+struct ImageSource {
+    queue: PathQueue,
+}
+
+impl ImageSource {
+    fn new(directory: &str) -> Result<Self, Box<dyn Error>> {
+        Self::with_extensions(directory, &["png"])
+    }
+
+    fn with_extensions(directory: &str, extensions: &[&str]) -> Result<Self, Box<dyn Error>> {
+        Self::from_patterns(directory, "*", extensions)
+    }
+
+    /// Drops paths before anything is decoded, e.g. images a checkpoint already lists.
+    #[cfg_attr(not(feature = "inference"), allow(dead_code))]
+    fn filter_paths(&self, keep: impl FnMut(&str) -> bool) -> Self {
+        Self { queue: self.queue.filter(keep) }
+    }
+
+    #[allow(dead_code)]
+    fn new_recursive(root: &str, extensions: &[&str]) -> Result<Self, Box<dyn Error>> {
+        Self::from_patterns(root, "**/*", extensions)
+    }
+
+    #[allow(dead_code)]
+    fn from_manifest(manifest_path: &str) -> Result<Self, Box<dyn Error>> {
+        let paths = fs::read_to_string(manifest_path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        Ok(Self { queue: PathQueue::new(paths) })
+    }
+
+    fn from_patterns(directory: &str, stem: &str, extensions: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let mut paths = Vec::new();
+        for extension in extensions {
+            paths.extend(
+                glob(&format!("{}/{}.{}", directory, stem, extension))?
+                    .filter_map(Result::ok)
+                    .filter(|p| p.is_file())
+                    .map(|p| p.display().to_string()),
+            );
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(Self { queue: PathQueue::new(paths) })
+    }
+}
+
+impl DataSource for ImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let path = self.queue.claim()?;
+        match image::open(&path) {
+            Ok(img) => Some(Ok((path, img))),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.queue.remaining())
+    }
+}
+
+/// Reads frames exported from Python as `.npy` arrays: uint8, C order, shaped `(H, W)` or
+/// `(H, W, C)` with 1, 3 (RGB) or 4 (RGBA) channels.
+#[derive(Clone)]
+struct NpyImageSource {
+    queue: PathQueue,
+}
+
+#[allow(dead_code)]
+impl NpyImageSource {
+    fn new(directory: &str) -> Result<Self, Box<dyn Error>> {
+        let source = ImageSource::with_extensions(directory, &["npy"])?;
+        Ok(Self { queue: source.queue })
+    }
+}
+
+impl DataSource for NpyImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let path = self.queue.claim()?;
+        let image = fs::read(&path)
+            .map_err(ProcessingError::from)
+            .and_then(|bytes| decode_npy(&bytes).map_err(|reason| npy_error(&path, &reason)));
+        Some(image.map(|image| (path, image)))
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.queue.remaining())
+    }
+}
+
+fn npy_error(path: &str, reason: &str) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path, reason),
+    ))
+}
+
+// Format: b"\x93NUMPY", major and minor version bytes, a little-endian header length (u16 in
+// version 1, u32 after), then a Python dict literal describing the array, then the raw data
+fn decode_npy(bytes: &[u8]) -> Result<DynamicImage, String> {
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err("not a .npy file".to_string());
+    }
+    let (header_len, header_start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        _ if bytes.len() >= 12 => (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12),
+        _ => return Err("truncated header".to_string()),
+    };
+    let data_start = header_start + header_len;
+    let header = bytes
+        .get(header_start..data_start)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or("truncated header")?;
+
+    let descr = npy_header_value(header, "descr").ok_or("header has no descr")?;
+    if !["'|u1'", "'<u1'", "'>u1'", "'u1'"].contains(&descr) {
+        return Err(format!("unsupported dtype {}, expected uint8", descr));
+    }
+    if npy_header_value(header, "fortran_order") == Some("True") {
+        return Err("Fortran-ordered arrays are not supported".to_string());
+    }
+    let shape: Vec<u32> = npy_header_value(header, "shape")
+        .ok_or("header has no shape")?
+        .trim_matches(|c: char| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| dimension.parse().map_err(|_| format!("bad shape dimension {:?}", dimension)))
+        .collect::<Result<_, _>>()?;
+
+    let (height, width, channels) = match shape[..] {
+        [height, width] => (height, width, 1),
+        [height, width, channels] => (height, width, channels),
+        _ => return Err(format!("expected an HW or HWC array, got shape {:?}", shape)),
+    };
+    let data = bytes[data_start..].to_vec();
+    let mismatch = || format!("data does not fill the {}x{}x{} shape", height, width, channels);
+    match channels {
+        1 => image::GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8).ok_or_else(mismatch),
+        3 => image::RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8).ok_or_else(mismatch),
+        4 => image::RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8).ok_or_else(mismatch),
+        _ => Err(format!("unsupported channel count {}, expected 1, 3 or 4", channels)),
+    }
+}
+
+// Looks up `'key': value` in the header dict; the shape tuple is returned with its parentheses
+fn npy_header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let value = header[start..].trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')')? + 1
+    } else {
+        value.find(|c: char| c == ',' || c == '}')?
+    };
+    Some(value[..end].trim())
+}
+
+#[derive(Clone)]
+struct AsyncImageSource {
+    queue: PathQueue,
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl AsyncImageSource {
+    #[allow(dead_code)]
+    fn new(directory: &str) -> Result<Self, Box<dyn Error>> {
+        ImageSource::new(directory).map(Self::from)
+    }
+}
+
+impl From<ImageSource> for AsyncImageSource {
+    fn from(source: ImageSource) -> Self {
+        Self { queue: source.queue }
+    }
+}
+
+#[async_trait]
+impl AsyncDataSource for AsyncImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let path = self.queue.claim()?;
+        let result = match tokio::fs::read(&path).await {
+            Ok(bytes) => image::load_from_memory(&bytes).map_err(ProcessingError::from),
+            Err(e) => Err(e.into()),
+        };
+        Some(result.map(|img| (path, img)))
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.queue.remaining())
+    }
+}
+
+#[derive(Deserialize)]
+struct ScreenshotPayload {
+    path: String,
+    data: String,
+}
+
+/// Polls an HTTP endpoint for screenshots; never runs dry, so the pipeline annotates until shut down.
+#[derive(Clone)]
+struct HttpPollingSource {
+    client: reqwest::Client,
+    endpoint: String,
+    poll_interval: Duration,
+    auth_token: Option<String>,
+}
+
+#[allow(dead_code)]
+impl HttpPollingSource {
+    fn new(endpoint: &str, poll_interval: Duration, auth_token: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.to_string(),
+            poll_interval,
+            auth_token,
+        }
+    }
+
+    // Ok(None) means the endpoint answered 204 and has nothing queued
+    async fn poll(&self) -> Result<Option<(String, DynamicImage)>, ProcessingError> {
+        let mut request = self.client.get(&self.endpoint);
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.map_err(http_error)?;
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        let payload: ScreenshotPayload = response
+            .error_for_status()
+            .map_err(http_error)?
+            .json()
+            .await
+            .map_err(http_error)?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&payload.data)
+            .map_err(|e| ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        let image = image::load_from_memory(&bytes)?;
+        Ok(Some((payload.path, image)))
+    }
+}
+
+fn http_error(error: reqwest::Error) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, error))
+}
+
+#[async_trait]
+impl AsyncDataSource for HttpPollingSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            match self.poll().await {
+                Ok(Some(data)) => return Some(Ok(data)),
+                Ok(None) => tokio::time::sleep(self.poll_interval).await,
+                Err(e) => {
+                    // Back off so an unreachable endpoint is not hammered in a tight loop
+                    tokio::time::sleep(self.poll_interval).await;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Downloads the images listed by a Postgres query. The query must return `(id TEXT, url TEXT)`
+/// rows; the id names the label file. It runs once, on the first read, and clones share its rows.
+#[derive(Clone)]
+struct PostgresImageSource {
+    config: tokio_postgres::Config,
+    query: String,
+    client: reqwest::Client,
+    // None until the query has run
+    rows: Arc<tokio::sync::Mutex<Option<VecDeque<(String, String)>>>>,
+}
+
+#[allow(dead_code)]
+impl PostgresImageSource {
+    fn new(connection_string: &str, query: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            config: connection_string.parse()?,
+            query: query.to_string(),
+            client: reqwest::Client::new(),
+            rows: Arc::new(tokio::sync::Mutex::new(None)),
+        })
+    }
+
+    async fn query_rows(&self) -> Result<VecDeque<(String, String)>, ProcessingError> {
+        let (client, connection) = self.config.connect(tokio_postgres::NoTls).await.map_err(postgres_error)?;
+        // The connection future drives the socket and must run alongside the client
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::error!(error = %e, "Postgres connection failed");
+            }
+        });
+        let rows = client.query(self.query.as_str(), &[]).await.map_err(postgres_error)?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0).map_err(postgres_error)?, row.try_get(1).map_err(postgres_error)?)))
+            .collect()
+    }
+
+    async fn download(&self, url: &str) -> Result<DynamicImage, ProcessingError> {
+        let bytes = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(http_error)?
+            .bytes()
+            .await
+            .map_err(http_error)?;
+        Ok(image::load_from_memory(&bytes)?)
+    }
+}
+
+fn postgres_error(error: tokio_postgres::Error) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, error))
+}
+
+#[async_trait]
+impl AsyncDataSource for PostgresImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let (id, url) = {
+            let mut rows = self.rows.lock().await;
+            if rows.is_none() {
+                match self.query_rows().await {
+                    Ok(queried) => *rows = Some(queried),
+                    Err(e) => {
+                        // Reported once; the other workers then see an exhausted source
+                        *rows = Some(VecDeque::new());
+                        return Some(Err(e));
+                    }
+                }
+            }
+            rows.as_mut()?.pop_front()?
+        };
+        Some(self.download(&url).await.map(|image| (id, image)))
+    }
+}
+
+/// Pops work items off a Redis list shared by annotation machines. An item is either a path
+/// readable on this machine or base64-encoded image bytes; the source ends once the list is empty.
+#[derive(Clone)]
+struct RedisQueueSource {
+    client: redis::Client,
+    queue_key: String,
+    connection: Arc<tokio::sync::OnceCell<redis::aio::MultiplexedConnection>>,
+}
+
+#[allow(dead_code)]
+impl RedisQueueSource {
+    fn new(redis_url: &str, queue_key: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            client: redis::Client::open(redis_url)?,
+            queue_key: queue_key.to_string(),
+            connection: Arc::new(tokio::sync::OnceCell::new()),
+        })
+    }
+
+    async fn pop(&self) -> Result<Option<String>, ProcessingError> {
+        let mut connection = self
+            .connection
+            .get_or_try_init(|| self.client.get_multiplexed_tokio_connection())
+            .await
+            .map_err(redis_error)?
+            .clone();
+        redis::cmd("LPOP")
+            .arg(&self.queue_key)
+            .query_async(&mut connection)
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn decode(item: String) -> Result<(String, DynamicImage), ProcessingError> {
+        if Path::new(&item).is_file() {
+            let bytes = tokio::fs::read(&item).await?;
+            return Ok((item, image::load_from_memory(&bytes)?));
+        }
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(item.trim())
+            .map_err(|e| ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        let image = image::load_from_memory(&bytes)?;
+        // Inline images have no name, so the content hash keeps labels from different machines apart
+        Ok((format!("redis_{}.png", &sha256_hex(&bytes)[..16]), image))
+    }
+}
+
+fn redis_error(error: redis::RedisError) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, error))
+}
+
+#[async_trait]
+impl AsyncDataSource for RedisQueueSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        match self.pop().await {
+            Ok(Some(item)) => Some(Self::decode(item).await),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Feeds items sent by any producer (a capture loop, a web handler) into the pipeline; it ends
+/// once every sender has been dropped and the buffered items are drained.
+struct ChannelDataSource<Item> {
+    receiver: Arc<tokio::sync::Mutex<mpsc::Receiver<(String, Item)>>>,
+}
+
+#[allow(dead_code)]
+impl<Item> ChannelDataSource<Item> {
+    fn new(receiver: mpsc::Receiver<(String, Item)>) -> Self {
+        Self {
+            receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+        }
+    }
+}
+
+// Derived Clone would needlessly require Item: Clone
+impl<Item> Clone for ChannelDataSource<Item> {
+    fn clone(&self) -> Self {
+        Self {
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<Item: Send + 'static> AsyncDataSource for ChannelDataSource<Item> {
+    type Item = Item;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        self.receiver.lock().await.recv().await.map(Ok)
+    }
+}
+
+/// Yields PNG/JPEG files as they appear in a directory, for annotating alongside a capture tool.
+#[derive(Clone)]
+struct WatchingImageSource {
+    // Dropping the watcher stops the notifications, so every clone keeps it alive
+    _watcher: Arc<notify::RecommendedWatcher>,
+    events: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<notify::Result<PathBuf>>>>,
+    seen: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+#[allow(dead_code)]
+impl WatchingImageSource {
+    fn new(directory: &str) -> Result<Self, Box<dyn Error>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let seen = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
+        let forget = Arc::clone(&seen);
+        let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            use notify::event::{AccessKind, AccessMode, EventKind, ModifyKind, RenameMode};
+            let event = match result {
+                Ok(event) => event,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            // Paths that left the directory are dropped from `seen`, so it only ever holds files
+            // that still exist; a name that comes back later is annotated again
+            let (arrived, departed): (&[PathBuf], &[PathBuf]) = match event.kind {
+                EventKind::Create(_)
+                | EventKind::Modify(ModifyKind::Data(_))
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => (&event.paths[..], &[]),
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => (&[], &event.paths[..]),
+                // Both carries [from, to]; capture tools often write a temp name and rename it into place
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                    (&event.paths[1..], &event.paths[..1])
+                }
+                _ => (&[], &[]),
+            };
+            if !departed.is_empty() {
+                if let Ok(mut seen) = forget.lock() {
+                    for path in departed {
+                        seen.remove(path);
+                    }
+                }
+            }
+            for path in arrived {
+                if is_watched_image(path) {
+                    let _ = tx.send(Ok(path.clone()));
+                }
+            }
+        })?;
+        watcher.watch(Path::new(directory), notify::RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            _watcher: Arc::new(watcher),
+            events: Arc::new(tokio::sync::Mutex::new(rx)),
+            seen,
+        })
+    }
+}
+
+fn is_watched_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| ["png", "jpg", "jpeg"].contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+#[async_trait]
+impl AsyncDataSource for WatchingImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            let path = match self.events.lock().await.recv().await? {
+                Ok(path) => path,
+                Err(e) => {
+                    return Some(Err(ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, e))))
+                }
+            };
+            match self.seen.lock() {
+                Ok(seen) if seen.contains(&path) => continue,
+                Ok(_) => {}
+                Err(e) => return Some(Err(ProcessingError::Lock(e.to_string()))),
+            }
+            // A file is reported while it is still being written; a failed decode is retried on
+            // the next write event for the same path instead of being surfaced as an error
+            let image = match tokio::fs::read(&path).await.map(|bytes| image::load_from_memory(&bytes)) {
+                Ok(Ok(image)) => image,
+                _ => continue,
+            };
+            let first = match self.seen.lock() {
+                Ok(mut seen) => seen.insert(path.clone()),
+                Err(e) => return Some(Err(ProcessingError::Lock(e.to_string()))),
+            };
+            // Another worker may have decoded the same file from an earlier event
+            if !first {
+                continue;
+            }
+            return Some(Ok((path.display().to_string(), image)));
+        }
+    }
+}
+
+#[cfg(feature = "inference")]
+#[derive(Clone)]
+struct VideoFileSource {
+    capture: Arc<Mutex<videoio::VideoCapture>>,
+    name: String,
+    frame_skip: usize,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl VideoFileSource {
+    fn new(path: &str, frame_skip: usize) -> Result<Self, Box<dyn Error>> {
+        let capture = videoio::VideoCapture::from_file(path, videoio::CAP_ANY)?;
+        if !capture.is_opened()? {
+            return Err(format!("Failed to open video {}", path).into());
+        }
+        let name = Path::new(path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("frame")
+            .to_string();
+        Ok(Self {
+            capture: Arc::new(Mutex::new(capture)),
+            name,
+            frame_skip,
+        })
+    }
+
+    fn next_frame(&self) -> Result<Option<(String, DynamicImage)>, ProcessingError> {
+        // Workers share one capture, so reading and skipping must happen under the same lock
+        let mut capture = self.capture.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        let mut frame = core::Mat::default();
+        if !capture.read(&mut frame)? || frame.empty()? {
+            return Ok(None);
+        }
+        let timestamp = capture.get(videoio::CAP_PROP_POS_MSEC)?;
+        for _ in 0..self.frame_skip {
+            if !capture.grab()? {
+                break;
+            }
+        }
+        drop(capture);
+
+        let image = mat_to_image(&frame)?;
+        Ok(Some((format!("{}_{}ms", self.name, timestamp as u64), image)))
+    }
+}
+
+#[cfg(feature = "inference")]
+impl DataSource for VideoFileSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        self.next_frame().transpose()
+    }
+}
+
+#[derive(Clone)]
+struct ZipImageSource {
+    archive: Arc<Mutex<zip::ZipArchive<File>>>,
+    entries: PathQueue,
+}
+
+#[allow(dead_code)]
+impl ZipImageSource {
+    fn new(zip_path: &str) -> Result<Self, Box<dyn Error>> {
+        let archive = zip::ZipArchive::new(File::open(zip_path)?)?;
+        let mut entries: Vec<String> = archive
+            .file_names()
+            .filter(|name| has_image_extension(name))
+            .map(str::to_string)
+            .collect();
+        entries.sort();
+        Ok(Self {
+            archive: Arc::new(Mutex::new(archive)),
+            entries: PathQueue::new(entries),
+        })
+    }
+
+    fn read_entry(&self, name: &str) -> Result<DynamicImage, ProcessingError> {
+        let mut archive = self.archive.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        let mut entry = archive.by_name(name).map_err(|e| ProcessingError::Io(e.into()))?;
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        image::load_from_memory(&bytes).map_err(ProcessingError::from)
+    }
+}
+
+impl DataSource for ZipImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let name = self.entries.claim()?;
+        Some(self.read_entry(&name).map(|img| (name, img)))
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.entries.remaining())
+    }
+}
+
+#[derive(Clone)]
+struct TarGzImageSource {
+    entries: Arc<Mutex<std::sync::mpsc::Receiver<Result<(String, Vec<u8>), ProcessingError>>>>,
+}
+
+#[allow(dead_code)]
+impl TarGzImageSource {
+    fn new(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        // tar can only be read front to back, so a reader thread streams entries through a bounded channel
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+        std::thread::spawn(move || {
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+            let entries = match archive.entries() {
+                Ok(entries) => entries,
+                Err(e) => {
+                    let _ = tx.send(Err(ProcessingError::Io(e)));
+                    return;
+                }
+            };
+            for entry in entries {
+                let result = entry.and_then(|mut entry| {
+                    let name = entry.path()?.display().to_string();
+                    if !entry.header().entry_type().is_file() || !has_image_extension(&name) {
+                        return Ok(None);
+                    }
+                    let mut bytes = Vec::new();
+                    entry.read_to_end(&mut bytes)?;
+                    Ok(Some((name, bytes)))
+                });
+                let message = match result {
+                    Ok(Some(data)) => Ok(data),
+                    Ok(None) => continue,
+                    Err(e) => Err(ProcessingError::Io(e)),
+                };
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            entries: Arc::new(Mutex::new(rx)),
+        })
+    }
+}
+
+impl DataSource for TarGzImageSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let received = match self.entries.lock() {
+            Ok(entries) => entries.recv().ok()?,
+            Err(e) => return Some(Err(ProcessingError::Lock(e.to_string()))),
+        };
+        Some(received.and_then(|(name, bytes)| {
+            image::load_from_memory(&bytes)
+                .map(|img| (name, img))
+                .map_err(ProcessingError::from)
+        }))
+    }
+}
+
+fn has_image_extension(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map_or(false, |extension| {
+            IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+}
+
+/// Skips images whose label file already exists under `label_dir`, named by `template`.
+/// `{index}` templates are rejected: their numbers follow completion order, so a path cannot be
+/// matched to its earlier label.
+#[derive(Clone)]
+struct DuplicateFilter<D: DataSource> {
+    inner: D,
+    label_dir: PathBuf,
+    template: OutputTemplate,
+}
+
+#[allow(dead_code)]
+impl<D: DataSource> DuplicateFilter<D> {
+    fn new(inner: D, label_dir: &Path, template: OutputTemplate) -> Result<Self, ProcessingError> {
+        if template.is_numbered() {
+            return Err(ProcessingError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "DuplicateFilter cannot match labels named with {index}",
+            )));
+        }
+        Ok(Self {
+            inner,
+            label_dir: label_dir.to_path_buf(),
+            template,
+        })
+    }
+
+    fn is_annotated(&self, path: &str) -> bool {
+        // Without an index segment the index argument is never rendered
+        self.label_dir.join(self.template.render_at(path, 0)).exists()
+    }
+}
+
+impl<D: DataSource> DataSource for DuplicateFilter<D> {
+    type Item = D::Item;
+    type Error = D::Error;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            match DataSource::get_data(&mut self.inner)? {
+                Ok((path, _)) if self.is_annotated(&path) => continue,
+                other => return Some(other),
+            }
+        }
+    }
+}
+
+/// Yields the images of an `ImageSource` in a seeded random order. Only the path list is
+/// shuffled; each image is decoded when it is handed out. A wrapper over any `DataSource` would
+/// have to decode the whole dataset up front to learn its order, so this takes the source's paths.
+#[derive(Clone)]
+struct ShufflingDataSource {
+    inner: ImageSource,
+}
+
+#[allow(dead_code)]
+impl ShufflingDataSource {
+    fn new(source: ImageSource, seed: u64) -> Self {
+        let mut paths = source.queue.paths().to_vec();
+        paths.shuffle(&mut StdRng::seed_from_u64(seed));
+        Self {
+            inner: ImageSource { queue: PathQueue::new(paths) },
+        }
+    }
+}
+
+impl DataSource for ShufflingDataSource {
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        DataSource::get_data(&mut self.inner)
+    }
+
+    fn len(&self) -> Option<usize> {
+        DataSource::len(&self.inner)
+    }
+}
+
+#[derive(Clone)]
+struct LimitedDataSource<D: DataSource> {
+    inner: D,
+    limit: usize,
+    // Shared by the worker clones, so the limit applies to the whole run
+    yielded: Arc<AtomicUsize>,
+}
+
+#[allow(dead_code)]
+impl<D: DataSource> LimitedDataSource<D> {
+    fn new(inner: D, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            yielded: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<D: DataSource> DataSource for LimitedDataSource<D> {
+    type Item = D::Item;
+    type Error = D::Error;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        if self.yielded.fetch_add(1, Ordering::Relaxed) >= self.limit {
+            return None;
+        }
+        self.inner.get_data()
+    }
+
+    fn len(&self) -> Option<usize> {
+        let remaining = self.limit.saturating_sub(self.yielded.load(Ordering::Relaxed));
+        Some(self.inner.len().map_or(remaining, |len| len.min(remaining)))
+    }
+}
+
+#[derive(Clone)]
+struct SamplingDataSource<D: DataSource> {
+    inner: D,
+    stride: usize,
+    counter: Arc<AtomicUsize>,
+}
+
+#[allow(dead_code)]
+impl<D: DataSource> SamplingDataSource<D> {
+    fn new(inner: D, stride: usize) -> Self {
+        Self {
+            inner,
+            stride: stride.max(1),
+            counter: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<D: DataSource> DataSource for SamplingDataSource<D> {
+    type Item = D::Item;
+    type Error = D::Error;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            let data = self.inner.get_data()?;
+            let keep = self.counter.fetch_add(1, Ordering::Relaxed) % self.stride == 0;
+            if keep {
+                return Some(data);
+            }
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        // Items already skipped past the last kept one shift the phase of the stride
+        let counter = self.counter.load(Ordering::Relaxed);
+        let offset = (self.stride - counter % self.stride) % self.stride;
+        self.inner
+            .len()
+            .map(|len| if len > offset { (len - offset + self.stride - 1) / self.stride } else { 0 })
+    }
+}
+
+// Uses existing label files to decide, so it is meant for curating already annotated data
+#[derive(Clone)]
+struct BalancedDataSource<D: DataSource> {
+    inner: D,
+    label_dir: PathBuf,
+    majority_class: u32,
+    ceiling: f32,
+    counts: Arc<Mutex<HashMap<u32, usize>>>,
+}
+
+#[allow(dead_code)]
+impl<D: DataSource> BalancedDataSource<D> {
+    fn new(inner: D, label_dir: &str, majority_class: u32, ceiling: f32) -> Self {
+        Self {
+            inner,
+            label_dir: PathBuf::from(label_dir),
+            majority_class,
+            ceiling,
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn accept(&self, path: &str) -> Result<bool, ProcessingError> {
+        // An image without a label file counts as having no boxes; a malformed one is an error
+        let label_path = Path::new(path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|stem| self.label_dir.join(format!("{}.txt", stem)))
+            .filter(|label_path| label_path.is_file());
+        let labels = match label_path {
+            Some(label_path) => read_yolo_labels(&label_path)?,
+            None => Vec::new(),
+        };
+
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(_) => return Ok(true),
+        };
+        let majority_in_image = labels.iter().filter(|d| d.class_id == self.majority_class).count();
+        if majority_in_image > 0 {
+            let total = counts.values().sum::<usize>() + labels.len();
+            let majority = counts.get(&self.majority_class).copied().unwrap_or(0) + majority_in_image;
+            if majority as f32 / total as f32 > self.ceiling {
+                return Ok(false);
+            }
+        }
+        for detection in &labels {
+            *counts.entry(detection.class_id).or_default() += 1;
+        }
+        Ok(true)
+    }
+}
+
+impl<D: DataSource> DataSource for BalancedDataSource<D>
+where
+    D::Error: From<ProcessingError>,
+{
+    type Item = D::Item;
+    type Error = D::Error;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            match self.inner.get_data()? {
+                Ok((path, item)) => match self.accept(&path) {
+                    Ok(true) => return Some(Ok((path, item))),
+                    Ok(false) => continue,
+                    Err(e) => return Some(Err(e.into())),
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct DeduplicationFilter<D> {
+    inner: D,
+    max_distance: u32,
+    seen: Arc<Mutex<HashSet<u64>>>,
+}
+
+#[allow(dead_code)]
+impl<D> DeduplicationFilter<D>
+where
+    D: AsyncDataSource<Item = DynamicImage, Error = ProcessingError>,
+{
+    fn new(inner: D, max_distance: u32) -> Self {
+        Self {
+            inner,
+            max_distance,
+            seen: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn is_duplicate(&self, hash: u64) -> Result<bool, ProcessingError> {
+        let mut seen = self.seen.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        if seen.iter().any(|other| (other ^ hash).count_ones() <= self.max_distance) {
+            return Ok(true);
+        }
+        seen.insert(hash);
+        Ok(false)
+    }
+}
+
+#[async_trait]
+impl<D> AsyncDataSource for DeduplicationFilter<D>
+where
+    D: AsyncDataSource<Item = DynamicImage, Error = ProcessingError>,
+{
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    async fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            let (path, image) = match self.inner.get_data().await? {
+                Ok(data) => data,
+                Err(e) => return Some(Err(e)),
+            };
+            let hashed = tokio::task::spawn_blocking(move || {
+                let hash = perceptual_hash(&image);
+                (image, hash)
+            })
+            .await;
+            let (image, hash) = match hashed {
+                Ok(hashed) => hashed,
+                Err(e) => return Some(Err(ProcessingError::Io(e.into()))),
+            };
+            match self.is_duplicate(hash) {
+                Ok(true) => tracing::debug!(path = %path, "Skipping near-duplicate image"),
+                Ok(false) => return Some(Ok((path, image))),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// DCT-based pHash: low-frequency 8x8 block of a 32x32 grayscale DCT, thresholded at the median
+fn perceptual_hash(image: &DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    const BLOCK: usize = 8;
+
+    let gray = image
+        .resize_exact(SIZE as u32, SIZE as u32, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f32> = gray.pixels().map(|p| p.0[0] as f32).collect();
+
+    let cosines: Vec<f32> = (0..SIZE * SIZE)
+        .map(|i| {
+            let (k, n) = (i / SIZE, i % SIZE);
+            (std::f32::consts::PI / SIZE as f32 * (n as f32 + 0.5) * k as f32).cos()
+        })
+        .collect();
+
+    // Separable DCT-II, rows first, keeping only the coefficients the hash needs
+    let mut rows = vec![0.0f32; SIZE * BLOCK];
+    for y in 0..SIZE {
+        for k in 0..BLOCK {
+            rows[y * BLOCK + k] = (0..SIZE).map(|x| pixels[y * SIZE + x] * cosines[k * SIZE + x]).sum();
+        }
+    }
+    let mut coefficients = Vec::with_capacity(BLOCK * BLOCK);
+    for k in 0..BLOCK {
+        for u in 0..BLOCK {
+            coefficients.push((0..SIZE).map(|y| rows[y * BLOCK + u] * cosines[k * SIZE + y]).sum::<f32>());
+        }
+    }
+
+    // The DC term only reflects overall brightness, so it neither sets a bit nor moves the
+    // median; the hash uses the 63 AC terms
+    let ac = &coefficients[1..];
+    let mut sorted = ac.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    ac.iter()
+        .enumerate()
+        .filter(|(_, value)| **value > median)
+        .fold(0u64, |hash, (bit, _)| hash | (1 << bit))
+}
+
+#[cfg(feature = "inference")]
+#[derive(Clone)]
+struct BlurFilter<D: DataSource> {
+    inner: D,
+    min_laplacian_variance: f64,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl<D> BlurFilter<D>
+where
+    D: DataSource<Item = DynamicImage, Error = ProcessingError>,
+{
+    fn new(inner: D, min_laplacian_variance: f64) -> Self {
+        Self {
+            inner,
+            min_laplacian_variance,
+        }
+    }
+}
+
+#[cfg(feature = "inference")]
+impl<D> DataSource for BlurFilter<D>
+where
+    D: DataSource<Item = DynamicImage, Error = ProcessingError>,
+{
+    type Item = DynamicImage;
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        loop {
+            let (path, image) = match self.inner.get_data()? {
+                Ok(data) => data,
+                Err(e) => return Some(Err(e)),
+            };
+            match laplacian_variance(&image) {
+                Ok(variance) if variance < self.min_laplacian_variance => {
+                    tracing::debug!(path = %path, variance, "Skipping blurry image");
+                }
+                Ok(_) => return Some(Ok((path, image))),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Pairs each image with its ground-truth YOLO labels from `label_dir`, matched by file stem,
+/// for evaluation and validation. An image without a label file is reported as an error.
+#[derive(Clone)]
+struct ZipDataSource<D: DataSource> {
+    inner: D,
+    label_dir: PathBuf,
+}
+
+#[allow(dead_code)]
+impl<D> ZipDataSource<D>
+where
+    D: DataSource<Item = DynamicImage, Error = ProcessingError>,
+{
+    fn new(image_source: D, label_dir: &Path) -> Self {
+        Self {
+            inner: image_source,
+            label_dir: label_dir.to_path_buf(),
+        }
+    }
+
+    fn load_labels(&self, image_path: &str) -> Result<Vec<Detection>, ProcessingError> {
+        let stem = Path::new(image_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| invalid_path(image_path))?;
+        read_yolo_labels(&self.label_dir.join(format!("{}.txt", stem)))
+    }
+}
+
+impl<D> DataSource for ZipDataSource<D>
+where
+    D: DataSource<Item = DynamicImage, Error = ProcessingError>,
+{
+    type Item = (DynamicImage, Vec<Detection>);
+    type Error = ProcessingError;
+
+    fn get_data(&mut self) -> Option<Result<(String, Self::Item), Self::Error>> {
+        let (path, image) = match self.inner.get_data()? {
+            Ok(data) => data,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.load_labels(&path).map(|labels| (path, (image, labels))))
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.inner.len()
+    }
+}
+
+#[cfg(feature = "inference")]
+// Sharp frames have strong edges, so the Laplacian response varies a lot
+fn laplacian_variance(image: &DynamicImage) -> Result<f64, ProcessingError> {
+    let mat = image_to_mat(image)?;
+    let mut gray = core::Mat::default();
+    imgproc::cvt_color(&mat, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+    let mut laplacian = core::Mat::default();
+    imgproc::laplacian(&gray, &mut laplacian, core::CV_64F, 1, 1.0, 0.0, core::BORDER_DEFAULT)?;
+
+    let mut mean = core::Vector::<f64>::new();
+    let mut stddev = core::Vector::<f64>::new();
+    core::mean_std_dev(&laplacian, &mut mean, &mut stddev, &core::Mat::default())?;
+    let deviation = stddev.get(0)?;
+    Ok(deviation * deviation)
+}
+
+#[cfg(feature = "inference")]
+/// Blacks out every pixel that matches a reference frame, so detection only sees what moved.
+/// Meant for window captures where the background never scrolls.
+#[derive(Clone)]
+struct BackgroundSubtractor {
+    background: core::Mat,
+    threshold: f64,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl BackgroundSubtractor {
+    fn new(background_image_path: &str) -> Result<Self, ProcessingError> {
+        let background = opencv::imgcodecs::imread(background_image_path, opencv::imgcodecs::IMREAD_COLOR)?;
+        if background.empty()? {
+            return Err(invalid_path(background_image_path));
+        }
+        Ok(Self {
+            background,
+            threshold: 25.0,
+        })
+    }
+
+    /// Minimum grayscale difference, in `0..=255`, for a pixel to count as foreground.
+    fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    fn apply(&self, frame: &core::Mat) -> Result<core::Mat, ProcessingError> {
+        if frame.size()? != self.background.size()? {
+            return Err(ProcessingError::InvalidDetection(format!(
+                "Frame is {:?} but the background is {:?}",
+                frame.size()?,
+                self.background.size()?
+            )));
+        }
+        let mut difference = core::Mat::default();
+        core::absdiff(frame, &self.background, &mut difference)?;
+        let mut gray = core::Mat::default();
+        imgproc::cvt_color(&difference, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+        let mut mask = core::Mat::default();
+        imgproc::threshold(&gray, &mut mask, self.threshold, 255.0, imgproc::THRESH_BINARY)?;
+
+        // copy_to allocates the destination zeroed, so pixels outside the mask stay black
+        let mut masked = core::Mat::default();
+        frame.copy_to_masked(&mut masked, &mask)?;
+        Ok(masked)
+    }
+}
+
+#[cfg(feature = "inference")]
+/// Finds moving regions in consecutive video frames against a running-average background.
+/// The rects it returns are meant for `ObjectDetectionTask::detect_in_regions`.
+struct MotionROIFilter {
+    // CV_32FC3 running average; None until the first frame arrives
+    background: Option<core::Mat>,
+    alpha: f64,
+    threshold: f64,
+}
+
+#[cfg(feature = "inference")]
+#[allow(dead_code)]
+impl MotionROIFilter {
+    /// `alpha` is the weight of each new frame in the background, so larger values forget
+    /// stationary objects faster.
+    fn new(alpha: f64) -> Self {
+        Self {
+            background: None,
+            alpha: alpha.clamp(0.0, 1.0),
+            threshold: 25.0,
+        }
+    }
+
+    fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Returns pixel rects of the areas that differ from the background, then folds `frame`
+    /// into it. The first frame only seeds the background and yields no regions.
+    fn regions(&mut self, frame: &DynamicImage) -> Result<Vec<core::Rect>, ProcessingError> {
+        let frame = image_to_mat(frame)?;
+        let mut frame_f32 = core::Mat::default();
+        frame.convert_to(&mut frame_f32, core::CV_32FC3, 1.0, 0.0)?;
+        let background = match &mut self.background {
+            Some(background) if background.size()? == frame_f32.size()? => background,
+            // A resolution change restarts the estimate, like the first frame
+            slot => {
+                *slot = Some(frame_f32);
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut reference = core::Mat::default();
+        background.convert_to(&mut reference, core::CV_8UC3, 1.0, 0.0)?;
+        let mut difference = core::Mat::default();
+        core::absdiff(&frame, &reference, &mut difference)?;
+        let mut gray = core::Mat::default();
+        imgproc::cvt_color(&difference, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+        let mut mask = core::Mat::default();
+        imgproc::threshold(&gray, &mut mask, self.threshold, 255.0, imgproc::THRESH_BINARY)?;
+
+        imgproc::accumulate_weighted(&frame_f32, background, self.alpha, &core::Mat::default())?;
+        mask_regions(&mask)
+    }
+}
+
+#[cfg(feature = "inference")]
+fn mat_to_image(mat: &core::Mat) -> Result<DynamicImage, ProcessingError> {
+    let mut rgb = core::Mat::default();
+    imgproc::cvt_color(mat, &mut rgb, imgproc::COLOR_BGR2RGB, 0)?;
+    let buffer = image::RgbImage::from_raw(rgb.cols() as u32, rgb.rows() as u32, rgb.data_bytes()?.to_vec())
+        .ok_or_else(|| {
+            ProcessingError::Image(image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            )))
+        })?;
+    Ok(DynamicImage::ImageRgb8(buffer))
+}
+
+#[cfg(feature = "inference")]
+fn image_to_mat(image: &DynamicImage) -> Result<core::Mat, ProcessingError> {
+    let rgb = image.to_rgb8();
+    let flat = core::Mat::from_slice(rgb.as_raw())?;
+    let shaped = flat.reshape(3, rgb.height() as i32)?;
+    let mut bgr = core::Mat::default();
+    imgproc::cvt_color(&shaped, &mut bgr, imgproc::COLOR_RGB2BGR, 0)?;
+    Ok(bgr)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProcessingSystemConfig {
+    channel_capacity: usize,
+    num_workers: usize,
+    dry_run: bool,
+}
+
+impl Default for ProcessingSystemConfig {
+    fn default() -> Self {
+        let num_workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+        Self {
+            channel_capacity: 100,
+            num_workers,
+            dry_run: false,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl ProcessingSystemConfig {
+    #[allow(dead_code)]
+    fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    fn with_num_workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = num_workers.max(1);
+        self
+    }
+
+    // Runs detection as usual but skips every filesystem write
+    fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    started: Option<Instant>,
+    images_processed: usize,
+    images_errored: usize,
+    total_detections: usize,
+    latencies_ms: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Metrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct MetricsReport {
+    images_processed: usize,
+    images_errored: usize,
+    total_detections: usize,
+    mean_latency_ms: f64,
+    p50_latency_ms: f64,
+    p99_latency_ms: f64,
+    images_per_second: f64,
+}
+
+impl Metrics {
+    fn start(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.started = Some(Instant::now());
+        }
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        if let Ok(mut state) = self.state.lock() {
+            state.latencies_ms.push(latency.as_secs_f64() * 1000.0);
+        }
+    }
+
+    fn record_success(&self, detections: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.images_processed += 1;
+            state.total_detections += detections;
+        }
+    }
+
+    fn record_error(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.images_errored += 1;
+        }
+    }
+
+    fn report(&self) -> MetricsReport {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return MetricsReport::default(),
+        };
+
+        let mut latencies = state.latencies_ms.clone();
+        latencies.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            if latencies.is_empty() {
+                0.0
+            } else {
+                latencies[((latencies.len() - 1) as f64 * p).round() as usize]
+            }
+        };
+        let mean_latency_ms = if latencies.is_empty() {
+            0.0
+        } else {
+            latencies.iter().sum::<f64>() / latencies.len() as f64
+        };
+        let elapsed = state.started.map_or(0.0, |started| started.elapsed().as_secs_f64());
+        let images_per_second = if elapsed > 0.0 {
+            state.images_processed as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        MetricsReport {
+            images_processed: state.images_processed,
+            images_errored: state.images_errored,
+            total_detections: state.total_detections,
+            mean_latency_ms,
+            p50_latency_ms: percentile(0.5),
+            p99_latency_ms: percentile(0.99),
+            images_per_second,
+        }
+    }
+}
+
+impl fmt::Display for MetricsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} processed, {} errored, {} detections, latency mean {:.1}ms p50 {:.1}ms p99 {:.1}ms, {:.2} images/s",
+            self.images_processed,
+            self.images_errored,
+            self.total_detections,
+            self.mean_latency_ms,
+            self.p50_latency_ms,
+            self.p99_latency_ms,
+            self.images_per_second
+        )
+    }
+}
+
+/// Detection counts per confidence bin, from 0 up to 1 in steps of `bin_width`.
+#[derive(Debug, Clone)]
+struct ConfidenceHistogram {
+    bin_width: f32,
+    counts: Vec<usize>,
+}
+
+impl ConfidenceHistogram {
+    fn compute(scores: &[f32], bin_width: f32) -> Self {
+        let bin_width = bin_width.clamp(0.01, 1.0);
+        let bins = (1.0 / bin_width).ceil() as usize;
+        let mut counts = vec![0; bins];
+        for score in scores {
+            // A score of exactly 1.0 belongs in the last bin rather than one past it
+            let bin = ((score.clamp(0.0, 1.0) / bin_width) as usize).min(bins - 1);
+            counts[bin] += 1;
+        }
+        Self { bin_width, counts }
+    }
+
+    fn bins(&self) -> impl Iterator<Item = (f32, f32, usize)> + '_ {
+        self.counts.iter().enumerate().map(|(i, &count)| {
+            let lower = i as f32 * self.bin_width;
+            (lower, (lower + self.bin_width).min(1.0), count)
+        })
+    }
+
+    fn write_csv(&self, path: &Path) -> Result<(), ProcessingError> {
+        let mut csv = String::from("lower,upper,count\n");
+        for (lower, upper, count) in self.bins() {
+            csv.push_str(&format!("{:.2},{:.2},{}\n", lower, upper, count));
+        }
+        fs::write(path, csv)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConfidenceHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        for (lower, upper, count) in self.bins() {
+            let bar = "#".repeat(count * 40 / max);
+            writeln!(f, "{:.2}-{:.2} {:>8} {}", lower, upper, count, bar)?;
+        }
+        Ok(())
+    }
+}
+
+/// `T` is the task's output type, so the same worker/collector loop can carry
+/// classification or segmentation results as well as detections.
+#[derive(Debug)]
+enum SystemMessage<T> {
+    ProcessingResult(Result<(String, T), ProcessingError>),
+    Skipped,
+    Metric(Duration),
+    Completed,
+}
+
+struct ProcessingSystem<T, D>
+where
+    T: Task<Input = DynamicImage, Error = ProcessingError> + Clone,
+    T::Output: AsRef<[Detection]> + Send,
+    D: AsyncDataSource<Item = DynamicImage, Error = ProcessingError> + Clone,
+{
+    task: T,
+    data_source: D,
+    sink: Arc<dyn OutputSink>,
+    checkpoint: Option<checkpoint::CheckpointManager>,
+    metrics: Metrics,
+    // Bin width and optional CSV destination; scores are only collected when this is set
+    histogram: Option<(f32, Option<PathBuf>)>,
+    confidences: Arc<Mutex<Vec<f32>>>,
+}
+
+// Only `annotate` drives the pipeline, and it needs `inference`
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl<T, D> ProcessingSystem<T, D>
+where
+    T: Task<Input = DynamicImage, Error = ProcessingError> + Clone,
+    T::Output: AsRef<[Detection]> + Send,
+    D: AsyncDataSource<Item = DynamicImage, Error = ProcessingError> + Clone,
+{
+    fn new(task: T, data_source: D) -> Self {
+        Self {
+            task,
+            data_source,
+            sink: Arc::new(YoloTxtSink::new(OutputConfig::default())),
+            checkpoint: None,
+            metrics: Metrics::default(),
+            histogram: None,
+            confidences: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Replaces the default YOLO TXT output; use a `MultiSink` to write several formats at once.
+    fn with_sink(mut self, sink: Box<dyn OutputSink>) -> Self {
+        self.sink = Arc::from(sink);
+        self
+    }
+
+    /// Skips images already listed in the checkpoint and records each newly saved one.
+    fn with_checkpoint(mut self, checkpoint: checkpoint::CheckpointManager) -> Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Logs the distribution of detection confidences once the run finishes, and writes it to
+    /// `csv` as well if given. Useful for picking a confidence threshold for a new dataset.
+    fn with_confidence_histogram(mut self, bin_width: f32, csv: Option<PathBuf>) -> Self {
+        self.histogram = Some((bin_width, csv));
+        self
+    }
+
+    async fn run(&mut self, config: ProcessingSystemConfig) {
+        let (tx, mut rx) = mpsc::channel::<SystemMessage<T::Output>>(config.channel_capacity);
+        self.metrics.start();
+        let progress = progress_bar(self.data_source.len());
+
+        let (shutdown_tx, _) = broadcast::channel::<()>(1);
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let signal_listener = tokio::spawn({
+            let shutdown_tx = shutdown_tx.clone();
+            let shutting_down = shutting_down.clone();
+            async move {
+                shutdown_signal().await;
+                tracing::warn!("Shutdown requested, waiting for in-flight images to finish");
+                shutting_down.store(true, Ordering::SeqCst);
+                let _ = shutdown_tx.send(());
+            }
+        });
+
+        for worker_id in 0..config.num_workers {
+            let tx = tx.clone();  // Removed unnecessary mut
+            let task = self.task.clone();
+            let mut data_source = self.data_source.clone();
+            let mut shutdown = shutdown_tx.subscribe();
+            let checkpoint = self.checkpoint.clone();
+
+            let worker = async move {
+                let batch_size = task.batch_size().max(1);
+                let mut batch = Vec::with_capacity(batch_size);
+                loop {
+                    // Only the wait for the next input is cancelled; an image already being processed runs to completion
+                    let data = tokio::select! {
+                        _ = shutdown.recv() => break,
+                        data = data_source.get_data() => data,
+                    };
+                    let Some(data) = data else { break };
+                    match data {
+                        Ok((path, _)) if is_checkpointed(checkpoint.as_ref(), &path).await => {
+                            tracing::debug!(path = %path, "Already annotated, skipping");
+                            let _ = tx.send(SystemMessage::Skipped).await;
+                        }
+                        Ok(item) => {
+                            batch.push(item);
+                            if batch.len() >= batch_size {
+                                dispatch_batch(&task, &tx, std::mem::take(&mut batch)).await;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!(error = %e, "Failed to read input");
+                            let _ = tx.send(SystemMessage::ProcessingResult(Err(e))).await;
+                        }
+                    }
+                }
+                // A partial batch is left over when the source runs dry or shutdown interrupts the reads
+                if !batch.is_empty() {
+                    dispatch_batch(&task, &tx, batch).await;
+                }
+                let _ = tx.send(SystemMessage::Completed).await;
+            };
+            tokio::spawn(worker.instrument(tracing::info_span!("worker", worker_id)));
+        }
+
+        let mut completed = 0;
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                SystemMessage::ProcessingResult(Ok((path, output))) => {
+                    let annotations = output.as_ref();
+                    self.metrics.record_success(annotations.len());
+                    exporter::COUNTERS.record_success(annotations.len());
+                    progress.inc(1);
+                    if self.histogram.is_some() {
+                        if let Ok(mut confidences) = self.confidences.lock() {
+                            confidences.extend(annotations.iter().map(|d| d.confidence));
+                        }
+                    }
+                    let detections = annotations.len();
+                    if config.dry_run {
+                        tracing::debug!(path = %path, detections, "Dry run, annotations not saved");
+                        continue;
+                    }
+                    if shutting_down.load(Ordering::SeqCst) {
+                        tracing::debug!(path = %path, detections, "Shutting down, annotations not saved");
+                        continue;
+                    }
+                    // Sinks do blocking file I/O, so they run on the blocking pool instead of this loop's thread
+                    let sink = Arc::clone(&self.sink);
+                    let written = tokio::task::spawn_blocking({
+                        let path = path.clone();
+                        move || sink.write(&path, output.as_ref())
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))));
+                    match written {
+                        Ok(()) => tracing::info!(path = %path, detections, "Annotations saved"),
+                        Err(e) => {
+                            tracing::error!(path = %path, error = %e, "Failed to save annotations");
+                            continue;
+                        }
+                    }
+                    if let Some(checkpoint) = self.checkpoint.clone() {
+                        // Usually only a set insert, but every SAVE_INTERVAL completions it rewrites the file
+                        let marked = tokio::task::spawn_blocking({
+                            let path = path.clone();
+                            move || checkpoint.mark_completed(&path)
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))));
+                        if let Err(e) = marked {
+                            tracing::error!(path = %path, error = %e, "Failed to update checkpoint");
+                        }
+                    }
+                }
+                SystemMessage::Skipped => {
+                    progress.inc(1);
+                }
+                SystemMessage::ProcessingResult(Err(_)) => {
+                    // Already logged by the worker, which still knows the image path
+                    self.metrics.record_error();
+                    exporter::COUNTERS.record_error();
+                    progress.inc(1);
+                }
+                SystemMessage::Metric(latency) => {
+                    self.metrics.record_latency(latency);
+                    exporter::COUNTERS.observe_inference(latency);
+                }
+                SystemMessage::Completed => {
+                    completed += 1;
+                    if completed == config.num_workers {
+                        break;
+                    }
+                }
+            }
+        }
+        signal_listener.abort();
+        progress.finish();
+
+        if !config.dry_run {
+            let sink = Arc::clone(&self.sink);
+            let flushed = tokio::task::spawn_blocking(move || sink.flush())
+                .await
+                .unwrap_or_else(|e| Err(ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))));
+            if let Err(e) = flushed {
+                tracing::error!(error = %e, "Failed to flush output sink");
+            }
+            // Also reached after a shutdown signal, so completions since the last periodic save are kept
+            if let Some(checkpoint) = &self.checkpoint {
+                if let Err(e) = checkpoint.save() {
+                    tracing::error!(error = %e, "Failed to save checkpoint");
+                }
+            }
+        }
+
+        if let Some((bin_width, csv)) = &self.histogram {
+            let histogram = match self.confidences.lock() {
+                Ok(confidences) => ConfidenceHistogram::compute(&confidences, *bin_width),
+                Err(_) => ConfidenceHistogram::compute(&[], *bin_width),
+            };
+            tracing::info!("Detection confidence distribution:\n{}", histogram);
+            if let Some(csv) = csv.as_deref().filter(|_| !config.dry_run) {
+                if let Err(e) = histogram.write_csv(csv) {
+                    tracing::error!(path = %csv.display(), error = %e, "Failed to write confidence histogram");
+                }
+            }
+        }
+
+        if config.dry_run {
+            let report = self.metrics.report();
+            tracing::info!(
+                images = report.images_processed,
+                detections = report.total_detections,
+                "Dry run complete, {} images would be annotated with {} detections",
+                report.images_processed,
+                report.total_detections
+            );
+        }
+    }
+}
+
+// Sources that can be filtered up front (see ImageSource::filter_paths) never reach this check
+// with a finished image; for the rest it is the fallback, and hashing the file blocks
+async fn is_checkpointed(checkpoint: Option<&checkpoint::CheckpointManager>, path: &str) -> bool {
+    let Some(checkpoint) = checkpoint.cloned() else { return false };
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || checkpoint.is_completed(&path)).await.unwrap_or(false)
+}
+
+async fn dispatch_batch<T>(task: &T, tx: &mpsc::Sender<SystemMessage<T::Output>>, batch: Vec<(String, DynamicImage)>)
+where
+    T: Task<Input = DynamicImage, Error = ProcessingError> + Clone,
+    T::Output: AsRef<[Detection]> + Send,
+{
+    let (paths, images): (Vec<String>, Vec<DynamicImage>) = batch.into_iter().unzip();
+    let spans: Vec<tracing::Span> = paths
+        .iter()
+        .map(|path| tracing::info_span!("image", image_path = %path, detection_count = tracing::field::Empty))
+        .collect();
+    for span in &spans {
+        span.in_scope(|| tracing::debug!("Processing image"));
+    }
+
+    let started = Instant::now();
+    // Tasks are synchronous and may block (inference, retry backoff, waiting for a pool replica),
+    // so they run on the blocking pool instead of a runtime worker
+    let count = images.len();
+    let task = task.clone();
+    let results = match tokio::task::spawn_blocking(move || task.process_batch(images)).await {
+        Ok(results) => results,
+        Err(e) => (0..count)
+            .map(|_| Err(ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))))
+            .collect(),
+    };
+    // One forward pass covers the whole batch, so each image is charged an equal share
+    let latency = started.elapsed() / paths.len().max(1) as u32;
+
+    for ((path, span), result) in paths.into_iter().zip(spans).zip(results) {
+        span.in_scope(|| match &result {
+            Ok(output) => {
+                span.record("detection_count", output.as_ref().len());
+            }
+            Err(e) => tracing::error!(error = %e, "Detection failed"),
+        });
+        let result = result.map(|annotations| (path, annotations));
+        async {
+            let _ = tx.send(SystemMessage::Metric(latency)).await;
+            let _ = tx.send(SystemMessage::ProcessingResult(result)).await;
+        }
+        .instrument(span)
+        .await;
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to install SIGTERM handler");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn progress_bar(total: Option<usize>) -> ProgressBar {
+    let target = if std::io::stdout().is_terminal() {
+        ProgressDrawTarget::stdout()
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    let progress = ProgressBar::with_draw_target(total.map(|n| n as u64), target);
+    if let Ok(style) = ProgressStyle::with_template("{bar:40} {pos}/{len} [{elapsed_precise}] ETA {eta}") {
+        progress.set_style(style);
+    }
+    progress
+}
+
+trait OutputSink: Send + Sync {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError>;
+
+    /// Called once after the last image; sinks that accumulate records write them here.
+    fn flush(&self) -> Result<(), ProcessingError> {
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+struct MultiSink(Vec<Box<dyn OutputSink>>);
+
+// Every sink is called even after one fails, so a broken preview cannot cost the labels a record;
+// the first error is returned and any later ones are logged
+impl OutputSink for MultiSink {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        first_error(self.0.iter().map(|sink| sink.write(image_path, labels)))
+    }
+
+    fn flush(&self) -> Result<(), ProcessingError> {
+        first_error(self.0.iter().map(|sink| sink.flush()))
+    }
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+fn first_error(results: impl Iterator<Item = Result<(), ProcessingError>>) -> Result<(), ProcessingError> {
+    let mut first = None;
+    for result in results {
+        match (result, &first) {
+            (Ok(()), _) => {}
+            (Err(e), None) => first = Some(e),
+            (Err(e), Some(_)) => tracing::error!(error = %e, "Output sink failed"),
+        }
+    }
+    first.map_or(Ok(()), Err)
+}
+
+/// Discards every detection, for measuring inference throughput without disk I/O.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(dead_code)]
+struct NullSink;
+
+impl OutputSink for NullSink {
+    fn write(&self, _image_path: &str, _labels: &[Detection]) -> Result<(), ProcessingError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OutputConfig {
+    label_dir: PathBuf,
+    image_copy_dir: Option<PathBuf>,
+    write_metadata: bool,
+    template: OutputTemplate,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            label_dir: PathBuf::from("./output/labels"),
+            image_copy_dir: None,
+            write_metadata: false,
+            template: OutputTemplate::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplateSegment {
+    Literal(String),
+    Stem,
+    Index { width: usize },
+    Date,
+}
+
+/// Names per-image output files from a pattern such as `{stem}_{index}.txt` or `{date}/{stem}.txt`.
+/// `{index}` counts written images from zero, padded to 6 digits (`{index:4}` for 4); `{date}` is
+/// today's local date as `YYYY-MM-DD`. Sinks that write another file type swap the extension.
+#[derive(Debug, Clone)]
+struct OutputTemplate {
+    segments: Vec<TemplateSegment>,
+    // Shared by clones so every worker draws from the same sequence
+    next_index: Arc<AtomicUsize>,
+}
+
+impl Default for OutputTemplate {
+    fn default() -> Self {
+        Self {
+            segments: vec![TemplateSegment::Stem, TemplateSegment::Literal(".txt".to_string())],
+            next_index: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl OutputTemplate {
+    fn parse(template: &str) -> Result<Self, ProcessingError> {
+        let invalid = |reason: String| {
+            ProcessingError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid output template {:?}: {}", template, reason),
+            ))
+        };
+
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(TemplateSegment::Literal(rest[..start].to_string()));
+            }
+            let end = start + rest[start..].find('}').ok_or_else(|| invalid("unclosed '{'".to_string()))?;
+            let token = &rest[start + 1..end];
+            segments.push(match token.split_once(':') {
+                None if token == "stem" => TemplateSegment::Stem,
+                None if token == "date" => TemplateSegment::Date,
+                None if token == "index" => TemplateSegment::Index { width: 6 },
+                Some(("index", width)) => TemplateSegment::Index {
+                    width: width.parse().map_err(|_| invalid(format!("bad index width {:?}", width)))?,
+                },
+                _ => return Err(invalid(format!("unknown token {{{}}}", token))),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(TemplateSegment::Literal(rest.to_string()));
+        }
+        if segments.is_empty() {
+            return Err(invalid("template is empty".to_string()));
+        }
+        Ok(Self {
+            segments,
+            next_index: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Returns a path relative to the sink's output directory; each call takes the next index.
+    fn render(&self, image_path: &str) -> PathBuf {
+        self.render_at(image_path, self.next_index.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Whether rendered names depend on the order images finish in.
+    fn is_numbered(&self) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, TemplateSegment::Index { .. }))
+    }
+
+    /// Renders without taking from the shared sequence, for predicting where a label would go.
+    fn render_at(&self, image_path: &str, index: usize) -> PathBuf {
+        // Sources such as HTTP or Redis may hand out names without a usable stem
+        let stem = Path::new(image_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("image_{:06}", index));
+
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Literal(text) => rendered.push_str(text),
+                TemplateSegment::Stem => rendered.push_str(&stem),
+                TemplateSegment::Index { width } => rendered.push_str(&format!("{:0width$}", index, width = *width)),
+                TemplateSegment::Date => rendered.push_str(&chrono::Local::now().format("%Y-%m-%d").to_string()),
+            }
+        }
+        PathBuf::from(rendered)
+    }
+}
+
+/// Copies `src` into `dest_dir` and returns where it landed. A same-named file of equal size is
+/// taken to be the same image and left alone; otherwise a `_1`, `_2`, ... suffix is appended.
+async fn copy_image_with_collision_handling(src: &Path, dest_dir: &Path) -> Result<PathBuf, ProcessingError> {
+    static COPY_SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = src.file_name().ok_or_else(|| invalid_path(&src.display().to_string()))?;
+    let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let extension = src.extension().and_then(|s| s.to_str());
+    let size = tokio::fs::metadata(src).await?.len();
+    tokio::fs::create_dir_all(dest_dir).await?;
+
+    // The copy is finished under a private name first, so any file another writer finds under a
+    // candidate name is already complete and its size can be trusted
+    let staged = dest_dir.join(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        COPY_SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::copy(src, &staged).await?;
+
+    let mut counter = 0;
+    let result = loop {
+        let candidate = if counter == 0 {
+            dest_dir.join(file_name)
+        } else {
+            match extension {
+                Some(extension) => dest_dir.join(format!("{}_{}.{}", stem, counter, extension)),
+                None => dest_dir.join(format!("{}_{}", stem, counter)),
+            }
+        };
+        match tokio::fs::metadata(&candidate).await {
+            Ok(existing) if existing.len() == size => break Ok(candidate),
+            Ok(_) => counter += 1,
+            // hard_link fails if the name was taken in the meantime, so two writers never share it
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => match tokio::fs::hard_link(&staged, &candidate).await {
+                Ok(()) => break Ok(candidate),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                // FAT/exFAT and some network mounts have no hard links (Linux reports EPERM there).
+                // A rename can replace a file created since the check above, but never exposes a
+                // partial copy, and it consumes the staged file
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::Unsupported | std::io::ErrorKind::PermissionDenied) => {
+                    tokio::fs::rename(&staged, &candidate).await?;
+                    return Ok(candidate);
+                }
+                Err(e) => break Err(e.into()),
+            },
+            Err(e) => break Err(e.into()),
+        }
+    };
+    tokio::fs::remove_file(&staged).await?;
+    result
+}
+
+fn create_parent_dir(path: &Path) -> Result<(), ProcessingError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Provenance written to the `.json` sidecar next to each label file.
+#[derive(Debug, Clone, Default)]
+struct AnnotationMetadata {
+    model_cfg: String,
+    model_weights: String,
+    confidence_threshold: f32,
+    nms_threshold: f32,
+    config_hash: String,
+}
+
+impl AnnotationMetadata {
+    fn to_json(&self, image_hash: Option<&str>) -> serde_json::Value {
+        json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "image_sha256": image_hash,
+            "model_cfg": self.model_cfg,
+            "model_weights": self.model_weights,
+            "confidence_threshold": self.confidence_threshold,
+            "nms_threshold": self.nms_threshold,
+            "config_sha256": self.config_hash,
+        })
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_image(path: &Path) -> Result<String, ProcessingError> {
+    Ok(sha256_hex(&fs::read(path)?))
+}
+
+#[derive(Debug, Clone)]
+struct YoloTxtSink {
+    output: OutputConfig,
+    write_confidence: bool,
+    metadata: AnnotationMetadata,
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl YoloTxtSink {
+    fn new(output: OutputConfig) -> Self {
+        Self {
+            output,
+            write_confidence: false,
+            metadata: AnnotationMetadata::default(),
+        }
+    }
+
+    fn with_confidence_column(mut self, enabled: bool) -> Self {
+        self.write_confidence = enabled;
+        self
+    }
+
+    /// Only written when `OutputConfig::write_metadata` is set.
+    fn with_metadata(mut self, metadata: AnnotationMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    fn save_labels(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        let source = Path::new(image_path);
+        let output_path = self.output.label_dir.join(self.output.template.render(image_path));
+        create_parent_dir(&output_path)?;
+
+        // Frames from video sources have no file on disk to copy
+        if let Some(copy_dir) = &self.output.image_copy_dir {
+            if source.is_file() {
+                // ProcessingSystem calls sinks on the blocking pool, where waiting on the runtime is allowed
+                tokio::runtime::Handle::current().block_on(copy_image_with_collision_handling(source, copy_dir))?;
+            }
+        }
+
+        // Written aside and renamed so a failed copy or crash never leaves a truncated label file
+        let mut staged = output_path.clone().into_os_string();
+        staged.push(".tmp");
+        fs::write(&staged, format_yolo_labels(labels, self.write_confidence))?;
+        fs::rename(&staged, &output_path)?;
+
+        if self.output.write_metadata {
+            // Video frames have no file on disk, so their sidecar carries no image hash
+            let image_hash = if source.is_file() { Some(hash_image(source)?) } else { None };
+            let sidecar = serde_json::to_vec_pretty(&self.metadata.to_json(image_hash.as_deref()))
+                .map_err(|e| ProcessingError::Io(e.into()))?;
+            fs::write(output_path.with_extension("json"), sidecar)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl OutputSink for YoloTxtSink {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        self.save_labels(image_path, labels)
+    }
+}
+
+#[derive(Clone)]
+struct PreviewSink {
+    preview_dir: PathBuf,
+    class_map: Arc<ClassMap>,
+    template: OutputTemplate,
+}
+
+#[cfg_attr(not(feature = "inference"), allow(dead_code))]
+impl PreviewSink {
+    fn new(preview_dir: &str, class_map: Option<Arc<ClassMap>>) -> Self {
+        Self {
+            preview_dir: PathBuf::from(preview_dir),
+            class_map: class_map.unwrap_or_default(),
+            template: OutputTemplate::default(),
+        }
+    }
+
+    /// The rendered extension is replaced by the source image's.
+    fn with_template(mut self, template: OutputTemplate) -> Self {
+        self.template = template;
+        self
+    }
+}
+
+impl OutputSink for PreviewSink {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        let source = Path::new(image_path);
+        // Frames from video sources have no file on disk to draw on
+        let extension = match source.extension() {
+            Some(extension) if source.is_file() => extension,
+            _ => return Ok(()),
+        };
+        let image = image::open(source)?;
+        let output_path = self.preview_dir.join(self.template.render(image_path)).with_extension(extension);
+        create_parent_dir(&output_path)?;
+        visualize::draw_detections(&image, labels, &self.class_map)
+            .save(output_path)
+            .map_err(ProcessingError::from)
+    }
+}
+
+fn format_yolo_labels(labels: &[Detection], write_confidence: bool) -> String {
+    let mut content = String::new();
+    for detection in labels {
+        content.push_str(&format!(
+            "{} {:.6} {:.6} {:.6} {:.6}",
+            detection.class_id, detection.x_center, detection.y_center, detection.width, detection.height
+        ));
+        if write_confidence {
+            content.push_str(&format!(" {:.6}", detection.confidence));
+        }
+        content.push('\n');
+    }
+    content
+}
+
+/// Streams YOLO label files into a single zip archive, opened on the first write.
+#[derive(Clone)]
+struct ZipLabelSink {
+    output_path: PathBuf,
+    archive: Arc<Mutex<Option<zip::ZipWriter<File>>>>,
+    template: OutputTemplate,
+    write_confidence: bool,
+}
+
+#[allow(dead_code)]
+impl ZipLabelSink {
+    fn new(output_path: PathBuf) -> Self {
+        Self {
+            output_path,
+            archive: Arc::new(Mutex::new(None)),
+            template: OutputTemplate::default(),
+            write_confidence: false,
+        }
+    }
+
+    fn with_template(mut self, template: OutputTemplate) -> Self {
+        self.template = template;
+        self
+    }
+
+    fn with_confidence_column(mut self, enabled: bool) -> Self {
+        self.write_confidence = enabled;
+        self
+    }
+}
+
+impl OutputSink for ZipLabelSink {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        let name = self.template.render(image_path);
+        let name = name.to_str().ok_or_else(|| invalid_path(image_path))?;
+        let content = format_yolo_labels(labels, self.write_confidence);
+        let mut archive = self.archive.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+
+        if archive.is_none() {
+            if let Some(parent) = self.output_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            *archive = Some(zip::ZipWriter::new(File::create(&self.output_path)?));
+        }
+        if let Some(archive) = archive.as_mut() {
+            let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+            archive.start_file(name, options).map_err(|e| ProcessingError::Io(e.into()))?;
+            archive.write_all(content.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the central directory; the archive is unreadable until this runs.
+    fn flush(&self) -> Result<(), ProcessingError> {
+        let mut archive = self.archive.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        if let Some(mut archive) = archive.take() {
+            archive.finish().map_err(|e| ProcessingError::Io(e.into()))?;
+        }
+        Ok(())
+    }
+}
+
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "bmp", "tif", "tiff", "webp"];
+
+fn split_dataset(label_dir: &Path, output_root: &Path, ratios: (f32, f32, f32), seed: u64) -> Result<(), Box<dyn Error>> {
+    for (label_path, split) in assign_splits(label_dir, ratios, seed)? {
+        let split_dir = output_root.join(split);
+        copy_label_pair(&label_path, &split_dir, &split_dir)?;
+    }
+    Ok(())
+}
+
+fn assign_splits(
+    label_dir: &Path,
+    ratios: (f32, f32, f32),
+    seed: u64,
+) -> Result<Vec<(PathBuf, &'static str)>, Box<dyn Error>> {
+    let (train_ratio, val_ratio, test_ratio) = ratios;
+    if [train_ratio, val_ratio, test_ratio].iter().any(|r| *r < 0.0)
+        || (train_ratio + val_ratio + test_ratio - 1.0).abs() > 1e-3
+    {
+        return Err(format!("Split ratios must be non-negative and sum to 1.0, got {:?}", ratios).into());
+    }
+
+    let mut labels: Vec<PathBuf> = glob(&format!("{}/*.txt", label_dir.display()))?
+        .filter_map(Result::ok)
+        .collect();
+    labels.sort();
+    labels.shuffle(&mut StdRng::seed_from_u64(seed));
+
+    let train_count = (labels.len() as f32 * train_ratio).round() as usize;
+    let val_count = ((labels.len() as f32 * val_ratio).round() as usize).min(labels.len() - train_count);
+
+    Ok(labels
+        .into_iter()
+        .enumerate()
+        .map(|(index, label_path)| {
+            let split = if index < train_count {
+                "train"
+            } else if index < train_count + val_count {
+                "val"
+            } else {
+                "test"
+            };
+            (label_path, split)
+        })
+        .collect())
+}
+
+fn copy_label_pair(label_path: &Path, label_out: &Path, image_out: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(label_out)?;
+    if let Some(file_name) = label_path.file_name() {
+        fs::copy(label_path, label_out.join(file_name))?;
+    }
+    match find_image_for_label(label_path) {
+        Some(image_path) => {
+            fs::create_dir_all(image_out)?;
+            if let Some(file_name) = image_path.file_name() {
+                fs::copy(&image_path, image_out.join(file_name))?;
+            }
+        }
+        None => tracing::warn!(label = %label_path.display(), "No image found for label"),
+    }
+    Ok(())
+}
+
+// Looks next to the label first, then in a sibling images/ directory
+fn find_image_for_label(label_path: &Path) -> Option<PathBuf> {
+    let stem = label_path.file_stem()?.to_str()?;
+    let label_dir = label_path.parent()?;
+    let mut candidates = vec![label_dir.to_path_buf()];
+    if let Some(root) = label_dir.parent() {
+        candidates.push(root.join("images"));
+    }
+    candidates.iter().find_map(|dir| {
+        IMAGE_EXTENSIONS
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", stem, extension)))
+            .find(|path| path.is_file())
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClassMap {
+    names: Vec<String>,
+}
+
+impl ClassMap {
+    fn new(names: Vec<String>) -> Self {
+        Self { names }
+    }
+
+    fn from_file(path: &str) -> Result<ClassMap, ProcessingError> {
+        let contents = fs::read_to_string(path)?;
+        // Line number is the class ID, so only trailing blank lines are dropped
+        let mut names: Vec<String> = contents.lines().map(|line| line.trim().to_string()).collect();
+        while names.last().map_or(false, |name| name.is_empty()) {
+            names.pop();
+        }
+        Ok(Self { names })
+    }
+
+    fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(id as usize).map(String::as_str)
+    }
+}
+
+fn class_name(class_map: Option<&ClassMap>, class_id: u32) -> String {
+    class_map
+        .and_then(|map| map.name(class_id))
+        .map(str::to_string)
+        .unwrap_or_else(|| class_id.to_string())
+}
+
+#[derive(Clone)]
+struct CocoOutputSink {
+    output_path: PathBuf,
+    class_map: Option<Arc<ClassMap>>,
+    // (path, width, height, labels); the size is read in `write` so one bad path cannot sink the flush
+    records: Arc<Mutex<Vec<(String, u32, u32, Vec<Detection>)>>>,
+}
+
+#[allow(dead_code)]
+impl CocoOutputSink {
+    fn new(output_dir: &str, class_map: Option<Arc<ClassMap>>) -> Self {
+        Self {
+            output_path: Path::new(output_dir).join("instances_train.json"),
+            class_map,
+            records: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn record(&self, image_path: &str, labels: Vec<Detection>) -> Result<(), ProcessingError> {
+        // Video frames and HTTP/Redis keys have no file to size; the other sinks skip them too
+        if !Path::new(image_path).is_file() {
+            tracing::warn!(path = %image_path, "Not an image file, left out of the COCO file");
+            return Ok(());
+        }
+        let (width, height) = image::image_dimensions(image_path)?;
+        let mut records = self.records.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        records.push((image_path.to_string(), width, height, labels));
+        Ok(())
+    }
+}
+
+impl OutputSink for CocoOutputSink {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        self.record(image_path, labels.to_vec())
+    }
+
+    fn flush(&self) -> Result<(), ProcessingError> {
+        let records = self.records.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        let mut images = Vec::with_capacity(records.len());
+        for (image_path, width, height, labels) in records.iter() {
+            images.push((image_path.as_str(), *width, *height, labels.as_slice()));
+        }
+        let document = coco_document(&images, self.class_map.as_deref());
+
+        if let Some(parent) = self.output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&self.output_path)?;
+        serde_json::to_writer_pretty(file, &document).map_err(|e| ProcessingError::Io(e.into()))?;
+        Ok(())
+    }
+}
+
+/// `images` are `(path, width, height, labels)`; only the file name of each path is recorded.
+fn coco_document(images: &[(&str, u32, u32, &[Detection])], class_map: Option<&ClassMap>) -> serde_json::Value {
+    let mut image_entries = Vec::new();
+    let mut annotations = Vec::new();
+    let mut class_ids = Vec::new();
+
+    for (image_index, &(image_path, width, height, labels)) in images.iter().enumerate() {
+        let image_id = image_index + 1;
+        let file_name = Path::new(image_path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or(image_path);
+
+        image_entries.push(json!({
+            "id": image_id,
+            "file_name": file_name,
+            "width": width,
+            "height": height,
+        }));
+
+        // COCO boxes are absolute [x_min, y_min, width, height]
+        for detection in labels {
+            let w = detection.width * width as f32;
+            let h = detection.height * height as f32;
+            let x = detection.x_center * width as f32 - w / 2.0;
+            let y = detection.y_center * height as f32 - h / 2.0;
+
+            annotations.push(json!({
+                "id": annotations.len() + 1,
+                "image_id": image_id,
+                "category_id": detection.class_id,
+                "bbox": [x, y, w, h],
+                "area": w * h,
+                "score": detection.confidence,
+                "iscrowd": 0,
+            }));
+            class_ids.push(detection.class_id);
+        }
+    }
+
+    class_ids.sort_unstable();
+    class_ids.dedup();
+    let categories: Vec<_> = class_ids
+        .iter()
+        .map(|&id| json!({ "id": id, "name": class_name(class_map, id) }))
+        .collect();
+
+    json!({
+        "images": image_entries,
+        "annotations": annotations,
+        "categories": categories,
+    })
+}
+
+/// Keeps annotations in a SQLite database so the dataset can be queried with SQL.
+struct SqliteOutputSink {
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[allow(dead_code)]
+impl SqliteOutputSink {
+    fn new(db_path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(parent) = db_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let connection = rusqlite::Connection::open(db_path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS images (
+                id INTEGER PRIMARY KEY,
+                path TEXT UNIQUE
+            );
+            CREATE TABLE IF NOT EXISTS detections (
+                id INTEGER PRIMARY KEY,
+                image_id INTEGER,
+                class_id INTEGER,
+                x_center REAL,
+                y_center REAL,
+                width REAL,
+                height REAL,
+                confidence REAL
+            );
+            CREATE INDEX IF NOT EXISTS detections_image_id ON detections (image_id);",
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+fn sqlite_error(error: rusqlite::Error) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::new(std::io::ErrorKind::Other, error))
+}
+
+impl OutputSink for SqliteOutputSink {
+    // Re-annotating an image replaces its detections rather than adding to them
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        let mut connection = self.connection.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        let transaction = connection.transaction().map_err(sqlite_error)?;
+        transaction
+            .execute("INSERT OR IGNORE INTO images (path) VALUES (?1)", [image_path])
+            .map_err(sqlite_error)?;
+        let image_id: i64 = transaction
+            .query_row("SELECT id FROM images WHERE path = ?1", [image_path], |row| row.get(0))
+            .map_err(sqlite_error)?;
+        transaction
+            .execute("DELETE FROM detections WHERE image_id = ?1", [image_id])
+            .map_err(sqlite_error)?;
+        {
+            let mut insert = transaction
+                .prepare(
+                    "INSERT INTO detections (image_id, class_id, x_center, y_center, width, height, confidence)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .map_err(sqlite_error)?;
+            for detection in labels {
+                insert
+                    .execute(rusqlite::params![
+                        image_id,
+                        detection.class_id,
+                        detection.x_center,
+                        detection.y_center,
+                        detection.width,
+                        detection.height,
+                        detection.confidence,
+                    ])
+                    .map_err(sqlite_error)?;
+            }
+        }
+        transaction.commit().map_err(sqlite_error)
+    }
+}
+
+#[derive(Clone)]
+struct PascalVocOutputSink {
+    output_dir: PathBuf,
+    class_map: Option<Arc<ClassMap>>,
+    template: OutputTemplate,
+}
+
+#[allow(dead_code)]
+impl PascalVocOutputSink {
+    fn new(output_dir: &str, class_map: Option<Arc<ClassMap>>) -> Self {
+        Self {
+            output_dir: PathBuf::from(output_dir),
+            class_map,
+            template: OutputTemplate::default(),
+        }
+    }
+
+    /// The rendered extension is replaced by `.xml`.
+    fn with_template(mut self, template: OutputTemplate) -> Self {
+        self.template = template;
+        self
+    }
+}
+
+impl OutputSink for PascalVocOutputSink {
+    // Blocking; call through tokio::task::spawn_blocking from async code
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        // VOC requires a <size>, which only an image file on disk can provide
+        if !Path::new(image_path).is_file() {
+            tracing::warn!(path = %image_path, "Not an image file, no VOC annotation written");
+            return Ok(());
+        }
+        let (width, height) = image::image_dimensions(image_path)?;
+        let xml = voc_xml(image_path, width, height, labels, self.class_map.as_deref());
+
+        let output_path = self.output_dir.join(self.template.render(image_path)).with_extension("xml");
+        create_parent_dir(&output_path)?;
+        fs::write(output_path, xml)?;
+        Ok(())
+    }
+}
+
+fn voc_xml(image_path: &str, width: u32, height: u32, labels: &[Detection], class_map: Option<&ClassMap>) -> String {
+    let path = Path::new(image_path);
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or(image_path);
+    let folder = path
+        .parent()
+        .and_then(|p| p.file_name())
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+
+    let mut xml = String::new();
+    xml.push_str("<annotation>\n");
+    xml.push_str(&format!("\t<folder>{}</folder>\n", xml_escape(folder)));
+    xml.push_str(&format!("\t<filename>{}</filename>\n", xml_escape(file_name)));
+    xml.push_str("\t<size>\n");
+    xml.push_str(&format!("\t\t<width>{}</width>\n", width));
+    xml.push_str(&format!("\t\t<height>{}</height>\n", height));
+    xml.push_str("\t\t<depth>3</depth>\n");
+    xml.push_str("\t</size>\n");
+
+    for detection in labels {
+        let name = class_name(class_map, detection.class_id);
+        let xmin = ((detection.x_center - detection.width / 2.0) * width as f32).round().max(0.0) as u32;
+        let ymin = ((detection.y_center - detection.height / 2.0) * height as f32).round().max(0.0) as u32;
+        let xmax = (((detection.x_center + detection.width / 2.0) * width as f32).round() as u32).min(width);
+        let ymax = (((detection.y_center + detection.height / 2.0) * height as f32).round() as u32).min(height);
+
+        xml.push_str("\t<object>\n");
+        xml.push_str(&format!("\t\t<name>{}</name>\n", xml_escape(&name)));
+        xml.push_str("\t\t<pose>Unspecified</pose>\n");
+        xml.push_str("\t\t<truncated>0</truncated>\n");
+        xml.push_str("\t\t<difficult>0</difficult>\n");
+        xml.push_str("\t\t<bndbox>\n");
+        xml.push_str(&format!("\t\t\t<xmin>{}</xmin>\n", xmin));
+        xml.push_str(&format!("\t\t\t<ymin>{}</ymin>\n", ymin));
+        xml.push_str(&format!("\t\t\t<xmax>{}</xmax>\n", xmax));
+        xml.push_str(&format!("\t\t\t<ymax>{}</ymax>\n", ymax));
+        xml.push_str("\t\t</bndbox>\n");
+        xml.push_str("\t</object>\n");
+    }
+    xml.push_str("</annotation>\n");
+    xml
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Minimal tf.train.Example schema, field tags match tensorflow/core/example/feature.proto
+#[derive(Clone, PartialEq, prost::Message)]
+struct TfExample {
+    #[prost(message, optional, tag = "1")]
+    features: Option<TfFeatures>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TfFeatures {
+    #[prost(map = "string, message", tag = "1")]
+    feature: HashMap<String, TfFeature>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TfFeature {
+    #[prost(oneof = "TfFeatureKind", tags = "1, 2, 3")]
+    kind: Option<TfFeatureKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum TfFeatureKind {
+    #[prost(message, tag = "1")]
+    BytesList(TfBytesList),
+    #[prost(message, tag = "2")]
+    FloatList(TfFloatList),
+    #[prost(message, tag = "3")]
+    Int64List(TfInt64List),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TfBytesList {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    value: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TfFloatList {
+    #[prost(float, repeated, tag = "1")]
+    value: Vec<f32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TfInt64List {
+    #[prost(int64, repeated, tag = "1")]
+    value: Vec<i64>,
+}
+
+impl TfFeature {
+    fn bytes(value: Vec<Vec<u8>>) -> Self {
+        Self { kind: Some(TfFeatureKind::BytesList(TfBytesList { value })) }
+    }
+
+    fn floats(value: Vec<f32>) -> Self {
+        Self { kind: Some(TfFeatureKind::FloatList(TfFloatList { value })) }
+    }
+
+    fn ints(value: Vec<i64>) -> Self {
+        Self { kind: Some(TfFeatureKind::Int64List(TfInt64List { value })) }
+    }
+}
+
+struct TfRecordShard {
+    writer: Option<BufWriter<File>>,
+    index: usize,
+    bytes_written: u64,
+}
+
+#[derive(Clone)]
+struct TfRecordSink {
+    output_prefix: PathBuf,
+    max_shard_bytes: u64,
+    class_map: Option<Arc<ClassMap>>,
+    shard: Arc<Mutex<TfRecordShard>>,
+}
+
+#[allow(dead_code)]
+impl TfRecordSink {
+    fn new(output_prefix: &str, max_shard_bytes: u64, class_map: Option<Arc<ClassMap>>) -> Self {
+        Self {
+            output_prefix: PathBuf::from(output_prefix),
+            max_shard_bytes,
+            class_map,
+            shard: Arc::new(Mutex::new(TfRecordShard {
+                writer: None,
+                index: 0,
+                bytes_written: 0,
+            })),
+        }
+    }
+
+    fn write_record(&self, data: &[u8]) -> Result<(), ProcessingError> {
+        let mut shard = self.shard.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+
+        if shard.writer.is_some() && shard.bytes_written >= self.max_shard_bytes {
+            if let Some(mut writer) = shard.writer.take() {
+                writer.flush()?;
+            }
+            shard.index += 1;
+            shard.bytes_written = 0;
+        }
+
+        if shard.writer.is_none() {
+            let shard_path = PathBuf::from(format!("{}-{:05}.tfrecord", self.output_prefix.display(), shard.index));
+            if let Some(parent) = shard_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = File::create(&shard_path)?;
+            shard.writer = Some(BufWriter::new(file));
+        }
+
+        // TFRecord framing: length, masked CRC of length, payload, masked CRC of payload
+        let length = (data.len() as u64).to_le_bytes();
+        let mut record = Vec::with_capacity(data.len() + 16);
+        record.extend_from_slice(&length);
+        record.extend_from_slice(&masked_crc32c(&length).to_le_bytes());
+        record.extend_from_slice(data);
+        record.extend_from_slice(&masked_crc32c(data).to_le_bytes());
+
+        if let Some(writer) = shard.writer.as_mut() {
+            writer.write_all(&record)?;
+        }
+        shard.bytes_written += record.len() as u64;
+        Ok(())
+    }
+}
+
+impl OutputSink for TfRecordSink {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        let path = Path::new(image_path);
+        // A record needs the encoded image, which only a file on disk provides
+        if !path.is_file() {
+            tracing::warn!(path = %image_path, "Not an image file, no TFRecord example written");
+            return Ok(());
+        }
+        let (width, height) = image::image_dimensions(path)?;
+        let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or(image_path);
+        let (encoded, format) = tf_encoded_image(fs::read(path)?)?;
+
+        let mut feature = HashMap::new();
+        feature.insert("image/height".to_string(), TfFeature::ints(vec![height as i64]));
+        feature.insert("image/width".to_string(), TfFeature::ints(vec![width as i64]));
+        feature.insert("image/filename".to_string(), TfFeature::bytes(vec![file_name.as_bytes().to_vec()]));
+        feature.insert("image/source_id".to_string(), TfFeature::bytes(vec![file_name.as_bytes().to_vec()]));
+        feature.insert("image/encoded".to_string(), TfFeature::bytes(vec![encoded]));
+        feature.insert("image/format".to_string(), TfFeature::bytes(vec![format.as_bytes().to_vec()]));
+        feature.insert(
+            "image/object/bbox/xmin".to_string(),
+            TfFeature::floats(labels.iter().map(|d| d.x_center - d.width / 2.0).collect()),
+        );
+        feature.insert(
+            "image/object/bbox/xmax".to_string(),
+            TfFeature::floats(labels.iter().map(|d| d.x_center + d.width / 2.0).collect()),
+        );
+        feature.insert(
+            "image/object/bbox/ymin".to_string(),
+            TfFeature::floats(labels.iter().map(|d| d.y_center - d.height / 2.0).collect()),
+        );
+        feature.insert(
+            "image/object/bbox/ymax".to_string(),
+            TfFeature::floats(labels.iter().map(|d| d.y_center + d.height / 2.0).collect()),
+        );
+        // The Object Detection API reserves label 0 for the background class
+        feature.insert(
+            "image/object/class/label".to_string(),
+            TfFeature::ints(labels.iter().map(|d| d.class_id as i64 + 1).collect()),
+        );
+        feature.insert(
+            "image/object/class/text".to_string(),
+            TfFeature::bytes(
+                labels
+                    .iter()
+                    .map(|d| class_name(self.class_map.as_deref(), d.class_id).into_bytes())
+                    .collect(),
+            ),
+        );
+
+        let example = TfExample {
+            features: Some(TfFeatures { feature }),
+        };
+        self.write_record(&example.encode_to_vec())
+    }
+
+    fn flush(&self) -> Result<(), ProcessingError> {
+        let mut shard = self.shard.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        if let Some(writer) = shard.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the bytes for `image/encoded` and the matching `image/format`. The Object Detection
+/// API expects `jpeg` or `png`, so any other format is re-encoded as PNG.
+fn tf_encoded_image(bytes: Vec<u8>) -> Result<(Vec<u8>, &'static str), ProcessingError> {
+    match image::guess_format(&bytes)? {
+        image::ImageFormat::Jpeg => Ok((bytes, "jpeg")),
+        image::ImageFormat::Png => Ok((bytes, "png")),
+        _ => {
+            let mut png = Vec::new();
+            image::load_from_memory(&bytes)?.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+            Ok((png, "png"))
+        }
+    }
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(data);
+    ((crc >> 15) | (crc << 17)).wrapping_add(0xa282_ead8)
+}
+
+#[derive(Clone)]
+struct LabelStudioExporter {
+    output_path: PathBuf,
+    class_map: Option<Arc<ClassMap>>,
+    records: Arc<Mutex<Vec<(String, u32, u32, Vec<Detection>)>>>,
+}
+
+#[allow(dead_code)]
+impl LabelStudioExporter {
+    fn new(output_path: &str, class_map: Option<Arc<ClassMap>>) -> Self {
+        Self {
+            output_path: PathBuf::from(output_path),
+            class_map,
+            records: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn record(&self, image_path: &str, labels: Vec<Detection>) -> Result<(), ProcessingError> {
+        if !Path::new(image_path).is_file() {
+            tracing::warn!(path = %image_path, "Not an image file, left out of the Label Studio tasks");
+            return Ok(());
+        }
+        let (width, height) = image::image_dimensions(image_path)?;
+        let mut records = self.records.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        records.push((image_path.to_string(), width, height, labels));
+        Ok(())
+    }
+}
+
+impl OutputSink for LabelStudioExporter {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        self.record(image_path, labels.to_vec())
+    }
+
+    fn flush(&self) -> Result<(), ProcessingError> {
+        let records = self.records.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+
+        let mut tasks = Vec::new();
+        for (image_path, width, height, labels) in records.iter() {
+            // Label Studio expects the top-left corner and size as percentages of the image
+            let result: Vec<_> = labels
+                .iter()
+                .enumerate()
+                .map(|(index, detection)| {
+                    json!({
+                        "id": format!("r{}", index),
+                        "type": "rectanglelabels",
+                        "from_name": "label",
+                        "to_name": "image",
+                        "original_width": width,
+                        "original_height": height,
+                        "image_rotation": 0,
+                        "score": detection.confidence,
+                        "value": {
+                            "x": (detection.x_center - detection.width / 2.0) * 100.0,
+                            "y": (detection.y_center - detection.height / 2.0) * 100.0,
+                            "width": detection.width * 100.0,
+                            "height": detection.height * 100.0,
+                            "rotation": 0,
+                            "rectanglelabels": [class_name(self.class_map.as_deref(), detection.class_id)],
+                        },
+                    })
+                })
+                .collect();
+
+            tasks.push(json!({
+                "data": { "image": image_path },
+                "annotations": [{ "result": result }],
+            }));
+        }
+
+        if let Some(parent) = self.output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&self.output_path)?;
+        serde_json::to_writer_pretty(file, &tasks).map_err(|e| ProcessingError::Io(e.into()))?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct CvatXmlExporter {
+    output_path: PathBuf,
+    class_map: ClassMap,
+    records: Arc<Mutex<Vec<(String, u32, u32, Vec<Detection>)>>>,
+}
+
+#[allow(dead_code)]
+impl CvatXmlExporter {
+    fn new(output_path: &str, class_names: Vec<String>) -> Self {
+        Self {
+            output_path: PathBuf::from(output_path),
+            class_map: ClassMap::new(class_names),
+            records: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn record(&self, image_path: &str, labels: Vec<Detection>) -> Result<(), ProcessingError> {
+        if !Path::new(image_path).is_file() {
+            tracing::warn!(path = %image_path, "Not an image file, left out of the CVAT annotations");
+            return Ok(());
+        }
+        let (width, height) = image::image_dimensions(image_path)?;
+        let mut records = self.records.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+        records.push((image_path.to_string(), width, height, labels));
+        Ok(())
+    }
+}
+
+impl OutputSink for CvatXmlExporter {
+    fn write(&self, image_path: &str, labels: &[Detection]) -> Result<(), ProcessingError> {
+        self.record(image_path, labels.to_vec())
+    }
+
+    fn flush(&self) -> Result<(), ProcessingError> {
+        let records = self.records.lock().map_err(|e| ProcessingError::Lock(e.to_string()))?;
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<annotations>\n");
+        xml.push_str("  <version>1.1</version>\n  <meta>\n    <task>\n      <labels>\n");
+        for name in &self.class_map.names {
+            xml.push_str(&format!(
+                "        <label>\n          <name>{}</name>\n          <type>rectangle</type>\n        </label>\n",
+                xml_escape(name)
+            ));
+        }
+        xml.push_str("      </labels>\n    </task>\n  </meta>\n");
+
+        for (image_id, (image_path, width, height, labels)) in records.iter().enumerate() {
+            let (width, height) = (*width, *height);
+            let file_name = Path::new(image_path)
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or(image_path);
+            xml.push_str(&format!(
+                "  <image id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n",
+                image_id,
+                xml_escape(file_name),
+                width,
+                height
+            ));
+            for detection in labels {
+                let xtl = ((detection.x_center - detection.width / 2.0) * width as f32).max(0.0);
+                let ytl = ((detection.y_center - detection.height / 2.0) * height as f32).max(0.0);
+                let xbr = ((detection.x_center + detection.width / 2.0) * width as f32).min(width as f32);
+                let ybr = ((detection.y_center + detection.height / 2.0) * height as f32).min(height as f32);
+                xml.push_str(&format!(
+                    "    <box label=\"{}\" source=\"auto\" occluded=\"0\" xtl=\"{:.2}\" ytl=\"{:.2}\" xbr=\"{:.2}\" ybr=\"{:.2}\" z_order=\"0\">\n    </box>\n",
+                    xml_escape(&class_name(Some(&self.class_map), detection.class_id)),
+                    xtl,
+                    ytl,
+                    xbr,
+                    ybr
+                ));
+            }
+            xml.push_str("  </image>\n");
+        }
+        xml.push_str("</annotations>\n");
+
+        if let Some(parent) = self.output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.output_path, xml)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct RoboflowExporter {
+    output_root: PathBuf,
+    class_map: Option<Arc<ClassMap>>,
+}
+
+#[allow(dead_code)]
+impl RoboflowExporter {
+    fn new(output_root: &str, class_map: Option<Arc<ClassMap>>) -> Self {
+        Self {
+            output_root: PathBuf::from(output_root),
+            class_map,
+        }
+    }
+
+    fn export(&self, label_dir: &Path, ratios: (f32, f32, f32), seed: u64) -> Result<(), Box<dyn Error>> {
+        let mut split_counts: HashMap<&str, usize> = HashMap::new();
+        let mut class_counts: HashMap<u32, usize> = HashMap::new();
+        let mut total_boxes = 0;
+
+        for (label_path, split) in assign_splits(label_dir, ratios, seed)? {
+            copy_label_pair(
+                &label_path,
+                &self.output_root.join("labels").join(split),
+                &self.output_root.join("images").join(split),
+            )?;
+            *split_counts.entry(split).or_default() += 1;
+
+            for detection in read_yolo_labels(&label_path)? {
+                *class_counts.entry(detection.class_id).or_default() += 1;
+                total_boxes += 1;
+            }
+        }
+
+        let class_count = match &self.class_map {
+            Some(map) => map.names.len(),
+            None => class_counts.keys().max().map_or(0, |max| *max as usize + 1),
+        };
+        let names: Vec<String> = (0..class_count as u32)
+            .map(|id| format!("'{}'", class_name(self.class_map.as_deref(), id).replace('\'', "''")))
+            .collect();
+
+        fs::create_dir_all(&self.output_root)?;
+        fs::write(
+            self.output_root.join("dataset.yaml"),
+            format!(
+                "train: images/train\nval: images/val\ntest: images/test\n\nnc: {}\nnames: [{}]\n",
+                class_count,
+                names.join(", ")
+            ),
+        )?;
+
+        let mut readme = String::from("Sky Force automated annotation dataset\n\n");
+        for split in ["train", "val", "test"] {
+            readme.push_str(&format!("{} images: {}\n", split, split_counts.get(split).copied().unwrap_or(0)));
+        }
+        readme.push_str(&format!("total boxes: {}\n\nboxes per class:\n", total_boxes));
+        let mut class_ids: Vec<&u32> = class_counts.keys().collect();
+        class_ids.sort();
+        for class_id in class_ids {
+            readme.push_str(&format!(
+                "  {} ({}): {}\n",
+                class_name(self.class_map.as_deref(), *class_id),
+                class_id,
+                class_counts[class_id]
+            ));
+        }
+        fs::write(self.output_root.join("README.dataset.txt"), readme)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Automated YOLO annotation of screenshots")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run object detection over the input images and write labels.
+    #[cfg(feature = "inference")]
+    Annotate {
+        /// Path to the pipeline configuration TOML file.
+        #[arg(long, default_value = "pipeline.toml")]
+        config: PathBuf,
+        /// Run inference without writing any output files.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check existing label files for malformed lines, unknown classes and duplicate boxes.
+    Validate {
+        #[arg(long)]
+        images: String,
+        #[arg(long)]
+        labels: String,
+        #[arg(long)]
+        num_classes: u32,
+        #[arg(long, default_value_t = 0.9)]
+        duplicate_iou: f32,
+    },
+    /// Check a label directory for malformed lines, bad coordinates and empty files.
+    Lint {
+        #[arg(long)]
+        labels: PathBuf,
+        #[arg(long)]
+        num_classes: usize,
+    },
+    /// Print per-class box counts and sizes for a label directory.
+    Stats {
+        #[arg(long)]
+        labels: PathBuf,
+        /// Also write the statistics to this CSV file.
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Print a histogram of box aspect ratios with this many bins.
+        #[arg(long)]
+        aspect_bins: Option<usize>,
+    },
+    /// Suggest Darknet anchor sizes by clustering the box sizes in a label directory.
+    Anchors {
+        #[arg(long)]
+        labels: PathBuf,
+        #[arg(long, default_value_t = 9)]
+        num_anchors: usize,
+        /// Network input size in pixels; anchors are printed at this scale.
+        #[arg(long, default_value_t = 416)]
+        image_size: u32,
+    },
+    /// Copy image/label pairs into train, val and test directories.
+    Split {
+        #[arg(long)]
+        labels: PathBuf,
+        #[arg(long)]
+        output: PathBuf,
+        #[arg(long, default_value_t = 0.8)]
+        train: f32,
+        #[arg(long, default_value_t = 0.1)]
+        val: f32,
+        #[arg(long, default_value_t = 0.1)]
+        test: f32,
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+    },
+    /// Rewrite a directory of labels from one annotation format into another.
+    Convert {
+        #[arg(long, value_enum)]
+        from: convert::LabelFormat,
+        #[arg(long, value_enum)]
+        to: convert::LabelFormat,
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long)]
+        output: PathBuf,
+        /// Class names file, one per line; maps COCO and VOC class names to IDs.
+        #[arg(long)]
+        classes: Option<String>,
+    },
+}
+
+#[cfg(feature = "inference")]
+async fn annotate(config_path: &Path, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let config = config::PipelineConfig::from_file(config_path)?;
+    let (model_cfg, model_weights) = config.model.model_files();
+    let metadata = AnnotationMetadata {
+        model_cfg: model_cfg.to_string(),
+        model_weights: model_weights.to_string(),
+        confidence_threshold: config.confidence_threshold,
+        nms_threshold: config.nms_threshold,
+        config_hash: sha256_hex(&fs::read(config_path)?),
+    };
+
+    let task = ObjectDetectionTask::from_model(
+        config.model,
+        config.input_width,
+        config.input_height,
+        BlobConfig::default(),
+        config.confidence_threshold,
+        config.nms_threshold,
+    )?
+    .with_batch_size(config.batch_size)
+    .with_backend(config.backend)?
+    .with_warmup(config.warmup);
+    let extensions: Vec<&str> = config.extensions.iter().map(String::as_str).collect();
+    let images = ImageSource::with_extensions(&config.input_dir, &extensions)?;
+    let output = OutputConfig {
+        label_dir: PathBuf::from(&config.output_dir),
+        write_metadata: config.write_metadata,
+        template: OutputTemplate::parse(&config.output_template)?,
+        ..OutputConfig::default()
+    };
+    let checkpoint = checkpoint::CheckpointManager::load(&Path::new(&config.output_dir).join("checkpoint.json"))?;
+    let data_source = if checkpoint.is_empty() {
+        AsyncImageSource::from(images)
+    } else {
+        tracing::info!(completed = checkpoint.len(), "Resuming from checkpoint");
+        // Finished images are dropped by path, so none of them is decoded again; hashing blocks
+        let checkpoint = checkpoint.clone();
+        let remaining =
+            tokio::task::spawn_blocking(move || images.filter_paths(|path| !checkpoint.is_completed(path))).await?;
+        AsyncImageSource::from(remaining)
+    };
+    let labels: Box<dyn OutputSink> = Box::new(
+        YoloTxtSink::new(output)
+            .with_confidence_column(config.write_confidence)
+            .with_metadata(metadata),
+    );
+    let sink: Box<dyn OutputSink> = match &config.preview_dir {
+        Some(preview_dir) => {
+            // A separate template keeps its own index sequence, so previews and labels share names
+            let template = OutputTemplate::parse(&config.output_template)?;
+            let preview = PreviewSink::new(preview_dir, None).with_template(template);
+            Box::new(MultiSink(vec![labels, Box::new(preview)]))
+        }
+        None => labels,
+    };
+    let mut system = ProcessingSystem::new(task, data_source)
+        .with_sink(sink)
+        .with_checkpoint(checkpoint);
+    if let Some(bin_width) = config.confidence_histogram {
+        let csv = Path::new(&config.output_dir).join("confidence_histogram.csv");
+        system = system.with_confidence_histogram(bin_width, Some(csv));
+    }
+
+    let metrics_server = config
+        .metrics_port
+        .map(|port| exporter::MetricsServer::start_on(std::net::SocketAddr::new(config.metrics_address, port)));
+
+    tracing::info!(config = %config_path.display(), "Starting automated annotation system...");
+    let system_config = ProcessingSystemConfig::default()
+        .with_num_workers(config.workers)
+        .with_dry_run(dry_run);
+    system.run(system_config).await;
+    tracing::info!(report = %system.metrics().report(), "Annotation run finished");
+    if let Some(metrics_server) = metrics_server {
+        metrics_server.abort();
+    }
+
+    Ok(())
+}
+
+fn validate(images: &str, labels: &str, num_classes: u32, duplicate_iou: f32) -> Result<(), Box<dyn Error>> {
+    let validator = LabelValidationTask::new(labels, num_classes, duplicate_iou);
+    let source = ImageSource::with_extensions(images, &IMAGE_EXTENSIONS)?;
+
+    let mut issue_count = 0;
+    for path in source.queue.paths() {
+        for issue in validator.validate(path)? {
+            println!("{}: {:?}", path, issue);
+            issue_count += 1;
+        }
+    }
+    println!("{} issues found in {} images", issue_count, source.queue.paths().len());
+    Ok(())
+}
+
+fn stats(labels: &Path, csv: Option<&Path>, aspect_bins: Option<usize>) -> Result<(), Box<dyn Error>> {
+    let stats = metrics::DatasetStats::compute(labels)?;
+    println!("{} label files", stats.image_count);
+    for (class_id, class) in &stats.per_class {
+        println!(
+            "class {}: {} boxes, width {:.4} ± {:.4}, height {:.4} ± {:.4}",
+            class_id, class.count, class.mean_width, class.std_width, class.mean_height, class.std_height
+        );
+    }
+    if let Some(bins) = aspect_bins {
+        println!("aspect ratio (width / height):");
+        for (start, end, count) in metrics::compute_box_aspect_ratio_histogram(labels, bins)? {
+            println!("  {:.3}-{:.3}: {}", start, end, count);
+        }
+    }
+    if let Some(csv) = csv {
+        stats.write_csv(csv)?;
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt::init();
+
+    match Cli::parse().command {
+        #[cfg(feature = "inference")]
+        Command::Annotate { config, dry_run } => annotate(&config, dry_run).await,
+        Command::Validate { images, labels, num_classes, duplicate_iou } => {
+            validate(&images, &labels, num_classes, duplicate_iou)
+        }
+        Command::Lint { labels, num_classes } => {
+            let errors = lint_labels(&labels, num_classes)?;
+            for error in &errors {
+                println!("{}", error);
+            }
+            println!("{} problems found in {}", errors.len(), labels.display());
+            Ok(())
+        }
+        Command::Stats { labels, csv, aspect_bins } => stats(&labels, csv.as_deref(), aspect_bins),
+        Command::Anchors { labels, num_anchors, image_size } => {
+            let anchors = metrics::suggest_anchors(&labels, num_anchors, image_size)?;
+            // Same layout as the `anchors=` line of a Darknet cfg
+            let line: Vec<String> = anchors.iter().map(|(w, h)| format!("{:.0},{:.0}", w, h)).collect();
+            println!("anchors = {}", line.join(", "));
+            Ok(())
+        }
+        Command::Split { labels, output, train, val, test, seed } => {
+            split_dataset(&labels, &output, (train, val, test), seed)
+        }
+        Command::Convert { from, to, input, output, classes } => {
+            let class_map = match classes {
+                Some(path) => ClassMap::from_file(&path)?,
+                None => ClassMap::default(),
+            };
+            convert::convert_labels(from, to, &input, &output, &class_map)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test writes into its own directory, so tests running in parallel never share files
+    fn scratch_file(test: &str, name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("skyfighterdataset-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn npy(version: u8, descr: &str, fortran_order: bool, shape: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+            descr,
+            if fortran_order { "True" } else { "False" },
+            shape
+        );
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY".to_vec();
+        bytes.extend_from_slice(&[version, 0]);
+        match version {
+            1 => bytes.extend_from_slice(&(header.len() as u16).to_le_bytes()),
+            _ => bytes.extend_from_slice(&(header.len() as u32).to_le_bytes()),
+        }
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn decode_npy_reads_a_v1_grayscale_array() {
+        let image = decode_npy(&npy(1, "|u1", false, "(2, 3)", &[0, 1, 2, 3, 4, 5])).unwrap();
+        let gray = image.as_luma8().expect("a single-channel array decodes to Luma8");
+        assert_eq!(gray.dimensions(), (3, 2));
+        assert_eq!(gray.get_pixel(2, 1).0, [5]);
+    }
+
+    #[test]
+    fn decode_npy_reads_a_v2_rgb_array() {
+        let data: Vec<u8> = (0..12).collect();
+        let image = decode_npy(&npy(2, "<u1", false, "(2, 2, 3)", &data)).unwrap();
+        let rgb = image.as_rgb8().expect("a 3-channel array decodes to Rgb8");
+        assert_eq!(rgb.dimensions(), (2, 2));
+        assert_eq!(rgb.get_pixel(1, 0).0, [3, 4, 5]);
+    }
+
+    #[test]
+    fn decode_npy_reads_an_rgba_array() {
+        let image = decode_npy(&npy(1, "|u1", false, "(1, 2, 4)", &[1, 2, 3, 4, 5, 6, 7, 8])).unwrap();
+        let rgba = image.as_rgba8().expect("a 4-channel array decodes to Rgba8");
+        assert_eq!(rgba.get_pixel(1, 0).0, [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn decode_npy_rejects_non_uint8_dtypes() {
+        let error = decode_npy(&npy(1, "<f4", false, "(1, 1)", &[0; 4])).unwrap_err();
+        assert!(error.contains("unsupported dtype"), "{}", error);
+    }
+
+    #[test]
+    fn decode_npy_rejects_fortran_order() {
+        let error = decode_npy(&npy(1, "|u1", true, "(2, 2)", &[0; 4])).unwrap_err();
+        assert!(error.contains("Fortran"), "{}", error);
+    }
+
+    #[test]
+    fn decode_npy_rejects_two_channel_arrays() {
+        let error = decode_npy(&npy(1, "|u1", false, "(1, 1, 2)", &[0; 2])).unwrap_err();
+        assert!(error.contains("channel count"), "{}", error);
+    }
+
+    #[test]
+    fn output_template_rejects_unknown_placeholders() {
+        let error = OutputTemplate::parse("{stem}_{frame}.txt").unwrap_err().to_string();
+        assert!(error.contains("unknown token {frame}"), "{}", error);
+    }
+
+    #[test]
+    fn output_template_rejects_an_unclosed_brace() {
+        let error = OutputTemplate::parse("{stem.txt").unwrap_err().to_string();
+        assert!(error.contains("unclosed"), "{}", error);
+    }
+
+    #[test]
+    fn output_template_numbers_images_in_order() {
+        let template = OutputTemplate::parse("{stem}_{index}.txt").unwrap();
+        assert!(template.is_numbered());
+        assert_eq!(template.render("images/a.jpg"), PathBuf::from("a_000000.txt"));
+        // Clones share the sequence, as the workers do
+        assert_eq!(template.clone().render("images/b.jpg"), PathBuf::from("b_000001.txt"));
+
+        let narrow = OutputTemplate::parse("{index:3}.txt").unwrap();
+        assert_eq!(narrow.render_at("a.jpg", 7), PathBuf::from("007.txt"));
+    }
+
+    #[test]
+    fn output_template_falls_back_to_the_index_without_a_stem() {
+        let template = OutputTemplate::default();
+        assert!(!template.is_numbered());
+        assert_eq!(template.render_at("", 42), PathBuf::from("image_000042.txt"));
+        assert_eq!(template.render_at("/", 3), PathBuf::from("image_000003.txt"));
+        assert_eq!(template.render_at("images/frame.jpg", 3), PathBuf::from("frame.txt"));
+    }
+
+    #[test]
+    fn lint_labels_reports_each_problem_with_its_line() {
+        let good = scratch_file("lint_labels", "good.txt", b"0 0.5 0.5 0.2 0.2\n1 0.5 0.5 0.2 0.2 0.9\n");
+        let label_dir = good.parent().unwrap();
+        let bad = scratch_file(
+            "lint_labels",
+            "bad.txt",
+            b"0 0.5 0.5 0.2\n\n0 1.5 0.5 0.2 0.2\ncat 0.5 0.5 0.2 0.2\n0 0.5 0.5 0.2 0.2 0.9 7\n5 0.5 0.5 0.2 0.2\n",
+        );
+
+        let errors = lint_labels(label_dir, 2).unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                LintError::WrongFieldCount { path: bad.clone(), line: 1, fields: 4 },
+                LintError::CoordinateOutOfRange { path: bad.clone(), line: 3, value: 1.5 },
+                LintError::Malformed {
+                    path: bad.clone(),
+                    line: 4,
+                    content: "cat 0.5 0.5 0.2 0.2".to_string(),
+                },
+                LintError::WrongFieldCount { path: bad.clone(), line: 5, fields: 7 },
+                LintError::ClassOutOfRange { path: bad, line: 6, class_id: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_yolo_labels_reports_the_malformed_line_number() {
+        let path = scratch_file("read_yolo_labels", "frame.txt", b"0 0.5 0.5 0.2 0.2\n\n1 0.5 0.5 oops 0.2\n");
+        let error = read_yolo_labels(&path).unwrap_err().to_string();
+        assert!(error.contains("frame.txt:3:"), "{}", error);
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use glob::glob;
+use crate::{read_yolo_labels, Detection};
+
+pub fn iou(a: &Detection, b: &Detection) -> f32 {
+    let left = (a.x_center - a.width / 2.0).max(b.x_center - b.width / 2.0);
+    let right = (a.x_center + a.width / 2.0).min(b.x_center + b.width / 2.0);
+    let top = (a.y_center - a.height / 2.0).max(b.y_center - b.height / 2.0);
+    let bottom = (a.y_center + a.height / 2.0).min(b.y_center + b.height / 2.0);
+    let intersection = (right - left).max(0.0) * (bottom - top).max(0.0);
+    let union = a.width * a.height + b.width * b.height - intersection;
+    if union <= 0.0 {
+        0.0
+    } else {
+        intersection / union
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn iou_matrix(detections: &[Detection]) -> Vec<Vec<f32>> {
+    detections
+        .iter()
+        .map(|a| detections.iter().map(|b| iou(a, b)).collect())
+        .collect()
+}
+
+const IOU_THRESHOLDS: [f32; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClassAp {
+    pub ap50: f32,
+    pub ap50_95: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MapResult {
+    pub per_class: HashMap<u32, ClassAp>,
+    pub map50: f32,
+    pub map50_95: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MapEvaluator {
+    images: Vec<(Vec<Detection>, Vec<Detection>)>,
+}
+
+#[allow(dead_code)]
+impl MapEvaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_image(&mut self, ground_truth: Vec<Detection>, predictions: Vec<Detection>) {
+        self.images.push((ground_truth, predictions));
+    }
+
+    pub fn compute(&self) -> MapResult {
+        // Like COCO, classes without any ground truth are left out of the mean
+        let mut class_ids: Vec<u32> = self
+            .images
+            .iter()
+            .flat_map(|(ground_truth, _)| ground_truth.iter().map(|d| d.class_id))
+            .collect();
+        class_ids.sort_unstable();
+        class_ids.dedup();
+
+        let mut result = MapResult::default();
+        for &class_id in &class_ids {
+            let aps: Vec<f32> = IOU_THRESHOLDS
+                .iter()
+                .map(|&threshold| self.average_precision(class_id, threshold))
+                .collect();
+            let class_ap = ClassAp {
+                ap50: aps[0],
+                ap50_95: aps.iter().sum::<f32>() / aps.len() as f32,
+            };
+            result.per_class.insert(class_id, class_ap);
+        }
+
+        if !class_ids.is_empty() {
+            let count = class_ids.len() as f32;
+            result.map50 = result.per_class.values().map(|ap| ap.ap50).sum::<f32>() / count;
+            result.map50_95 = result.per_class.values().map(|ap| ap.ap50_95).sum::<f32>() / count;
+        }
+        result
+    }
+
+    fn average_precision(&self, class_id: u32, threshold: f32) -> f32 {
+        let ground_truth: Vec<Vec<&Detection>> = self
+            .images
+            .iter()
+            .map(|(ground_truth, _)| ground_truth.iter().filter(|d| d.class_id == class_id).collect())
+            .collect();
+        let total_ground_truth: usize = ground_truth.iter().map(Vec::len).sum();
+        if total_ground_truth == 0 {
+            return 0.0;
+        }
+
+        let mut predictions: Vec<(usize, &Detection)> = self
+            .images
+            .iter()
+            .enumerate()
+            .flat_map(|(image, (_, predictions))| {
+                predictions
+                    .iter()
+                    .filter(|d| d.class_id == class_id)
+                    .map(move |d| (image, d))
+            })
+            .collect();
+        predictions.sort_by(|a, b| b.1.confidence.total_cmp(&a.1.confidence));
+
+        let mut matched: Vec<Vec<bool>> = ground_truth.iter().map(|boxes| vec![false; boxes.len()]).collect();
+        let mut true_positives = 0usize;
+        let mut precisions = Vec::with_capacity(predictions.len());
+        let mut recalls = Vec::with_capacity(predictions.len());
+
+        for (rank, (image, prediction)) in predictions.iter().enumerate() {
+            // Greedily match the highest-IoU ground truth box that is still free
+            let best = ground_truth[*image]
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !matched[*image][*index])
+                .map(|(index, truth)| (index, iou(prediction, truth)))
+                .filter(|(_, overlap)| *overlap >= threshold)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+
+            if let Some((index, _)) = best {
+                matched[*image][index] = true;
+                true_positives += 1;
+            }
+            precisions.push(true_positives as f32 / (rank + 1) as f32);
+            recalls.push(true_positives as f32 / total_ground_truth as f32);
+        }
+
+        // Precision envelope, then 101-point interpolation over recall
+        for i in (0..precisions.len().saturating_sub(1)).rev() {
+            precisions[i] = precisions[i].max(precisions[i + 1]);
+        }
+        let mut total = 0.0;
+        for step in 0..=100 {
+            let recall_level = step as f32 / 100.0;
+            if let Some(position) = recalls.iter().position(|&recall| recall >= recall_level) {
+                total += precisions[position];
+            }
+        }
+        total / 101.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClassStats {
+    pub count: usize,
+    pub mean_width: f32,
+    pub std_width: f32,
+    pub mean_height: f32,
+    pub std_height: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DatasetStats {
+    pub image_count: usize,
+    pub per_class: BTreeMap<u32, ClassStats>,
+}
+
+impl DatasetStats {
+    pub fn compute(label_dir: &Path) -> Result<DatasetStats, Box<dyn Error>> {
+        let mut image_count = 0;
+        let mut sizes: BTreeMap<u32, Vec<(f32, f32)>> = BTreeMap::new();
+
+        for label_path in glob(&format!("{}/*.txt", label_dir.display()))?.filter_map(Result::ok) {
+            image_count += 1;
+            for detection in read_yolo_labels(&label_path)? {
+                sizes
+                    .entry(detection.class_id)
+                    .or_default()
+                    .push((detection.width, detection.height));
+            }
+        }
+
+        let per_class = sizes
+            .into_iter()
+            .map(|(class_id, boxes)| {
+                let (mean_width, std_width) = mean_and_std(boxes.iter().map(|b| b.0));
+                let (mean_height, std_height) = mean_and_std(boxes.iter().map(|b| b.1));
+                let stats = ClassStats {
+                    count: boxes.len(),
+                    mean_width,
+                    std_width,
+                    mean_height,
+                    std_height,
+                };
+                (class_id, stats)
+            })
+            .collect();
+
+        Ok(DatasetStats { image_count, per_class })
+    }
+
+    pub fn write_csv(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut csv = String::from("class_id,count,mean_width,std_width,mean_height,std_height\n");
+        for (class_id, stats) in &self.per_class {
+            csv.push_str(&format!(
+                "{},{},{:.6},{:.6},{:.6},{:.6}\n",
+                class_id, stats.count, stats.mean_width, stats.std_width, stats.mean_height, stats.std_height
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, csv)?;
+        Ok(())
+    }
+}
+
+/// Histogram of box `width / height` ratios across every label in `label_dir`, with `bins`
+/// equal-width bins spanning the smallest to the largest ratio. Ratios are taken from the
+/// normalized YOLO sizes, so they match pixel ratios only for square images.
+pub fn compute_box_aspect_ratio_histogram(label_dir: &Path, bins: usize) -> Result<Vec<(f32, f32, usize)>, Box<dyn Error>> {
+    let ratios: Vec<f32> = read_boxes(label_dir)?
+        .iter()
+        .filter(|d| d.width > 0.0 && d.height > 0.0)
+        .map(|d| d.width / d.height)
+        .collect();
+    if ratios.is_empty() || bins == 0 {
+        return Ok(Vec::new());
+    }
+
+    let min = ratios.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = ratios.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    // All boxes share one ratio; give the single occupied bin a nonzero width
+    let bin_width = if max > min { (max - min) / bins as f32 } else { 1.0 };
+    let mut counts = vec![0; bins];
+    for ratio in ratios {
+        let bin = (((ratio - min) / bin_width) as usize).min(bins - 1);
+        counts[bin] += 1;
+    }
+    Ok(counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| (min + i as f32 * bin_width, min + (i + 1) as f32 * bin_width, count))
+        .collect())
+}
+
+/// Clusters the box sizes in `label_dir` into `num_anchors` Darknet anchors, in pixels for a
+/// square `image_size` network input and sorted by area. Uses k-means with `1 - IoU` as the
+/// distance, as in YOLOv2, so large boxes do not dominate the clustering.
+pub fn suggest_anchors(label_dir: &Path, num_anchors: usize, image_size: u32) -> Result<Vec<(f32, f32)>, Box<dyn Error>> {
+    let mut sizes: Vec<(f32, f32)> = read_boxes(label_dir)?
+        .iter()
+        .filter(|d| d.width > 0.0 && d.height > 0.0)
+        .map(|d| (d.width, d.height))
+        .collect();
+    if sizes.len() < num_anchors {
+        return Err(format!("Only {} boxes found, need at least {} to suggest anchors", sizes.len(), num_anchors).into());
+    }
+    if num_anchors == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Seeding from evenly spaced area quantiles keeps the result deterministic
+    sizes.sort_by(|a, b| (a.0 * a.1).total_cmp(&(b.0 * b.1)));
+    let mut centroids: Vec<(f32, f32)> = (0..num_anchors)
+        .map(|i| sizes[(2 * i + 1) * sizes.len() / (2 * num_anchors)])
+        .collect();
+    let mut assignments = vec![usize::MAX; sizes.len()];
+
+    for _ in 0..300 {
+        let mut changed = false;
+        for (size, assignment) in sizes.iter().zip(assignments.iter_mut()) {
+            let nearest = centroids
+                .iter()
+                .enumerate()
+                .max_by(|a, b| size_iou(*size, *a.1).total_cmp(&size_iou(*size, *b.1)))
+                .map_or(0, |(index, _)| index);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        for (index, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<(f32, f32)> = sizes
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assignment)| **assignment == index)
+                .map(|(size, _)| *size)
+                .collect();
+            // An empty cluster keeps its previous centroid
+            if !members.is_empty() {
+                let count = members.len() as f32;
+                *centroid = (
+                    members.iter().map(|m| m.0).sum::<f32>() / count,
+                    members.iter().map(|m| m.1).sum::<f32>() / count,
+                );
+            }
+        }
+    }
+
+    let scale = image_size as f32;
+    let mut anchors: Vec<(f32, f32)> = centroids.into_iter().map(|(w, h)| (w * scale, h * scale)).collect();
+    anchors.sort_by(|a, b| (a.0 * a.1).total_cmp(&(b.0 * b.1)));
+    Ok(anchors)
+}
+
+// IoU of two boxes sharing a center, which is all that matters for anchor shapes
+fn size_iou(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let intersection = a.0.min(b.0) * a.1.min(b.1);
+    intersection / (a.0 * a.1 + b.0 * b.1 - intersection)
+}
+
+fn read_boxes(label_dir: &Path) -> Result<Vec<Detection>, Box<dyn Error>> {
+    let mut boxes = Vec::new();
+    for label_path in glob(&format!("{}/*.txt", label_dir.display()))?.filter_map(Result::ok) {
+        boxes.extend(read_yolo_labels(&label_path)?);
+    }
+    Ok(boxes)
+}
+
+fn mean_and_std(values: impl Iterator<Item = f32> + Clone) -> (f32, f32) {
+    let count = values.clone().count();
+    if count == 0 {
+        return (0.0, 0.0);
+    }
+    let mean = values.clone().sum::<f32>() / count as f32;
+    let variance = values.map(|v| (v - mean).powi(2)).sum::<f32>() / count as f32;
+    (mean, variance.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(x_center: f32, y_center: f32, width: f32, height: f32) -> Detection {
+        Detection {
+            class_id: 0,
+            confidence: 1.0,
+            x_center,
+            y_center,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn iou_of_disjoint_boxes_is_zero() {
+        let a = detection(0.2, 0.2, 0.2, 0.2);
+        let b = detection(0.8, 0.8, 0.2, 0.2);
+        assert_eq!(iou(&a, &b), 0.0);
+    }
+
+    #[test]
+    fn iou_of_identical_boxes_is_one() {
+        let a = detection(0.5, 0.5, 0.4, 0.3);
+        assert!((iou(&a, &a) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn iou_of_partial_overlap() {
+        // Two 0.4 x 0.4 boxes offset by half a width: intersection 0.08, union 0.24
+        let a = detection(0.4, 0.5, 0.4, 0.4);
+        let b = detection(0.6, 0.5, 0.4, 0.4);
+        assert!((iou(&a, &b) - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn iou_with_zero_area_box_is_zero() {
+        let point = detection(0.5, 0.5, 0.0, 0.0);
+        let a = detection(0.5, 0.5, 0.4, 0.4);
+        assert_eq!(iou(&point, &a), 0.0);
+        assert_eq!(iou(&point, &point), 0.0);
+    }
+
+    #[test]
+    fn iou_matrix_is_symmetric_with_unit_diagonal() {
+        let boxes = [detection(0.4, 0.5, 0.4, 0.4), detection(0.6, 0.5, 0.4, 0.4), detection(0.9, 0.9, 0.1, 0.1)];
+        let matrix = iou_matrix(&boxes);
+        for i in 0..boxes.len() {
+            assert!((matrix[i][i] - 1.0).abs() < 1e-6);
+            for j in 0..boxes.len() {
+                assert_eq!(matrix[i][j], matrix[j][i]);
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::OnceLock;
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+use crate::{class_name, ClassMap, Detection};
+
+const PALETTE: [[u8; 3]; 8] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [255, 225, 25],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+];
+
+const FONT_PATHS: [&str; 4] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:/Windows/Fonts/arial.ttf",
+];
+
+// No font ships with the crate, so labels are only drawn when a system font is found
+fn label_font() -> Option<&'static Font<'static>> {
+    static FONT: OnceLock<Option<Font<'static>>> = OnceLock::new();
+    FONT.get_or_init(|| {
+        FONT_PATHS
+            .iter()
+            .filter_map(|path| std::fs::read(path).ok())
+            .find_map(Font::try_from_vec)
+    })
+    .as_ref()
+}
+
+pub fn draw_detections(image: &DynamicImage, detections: &[Detection], class_names: &ClassMap) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let mut canvas: RgbImage = image.to_rgb8();
+
+    for detection in detections {
+        let color = Rgb(PALETTE[detection.class_id as usize % PALETTE.len()]);
+        let left = ((detection.x_center - detection.width / 2.0) * width as f32).max(0.0) as i32;
+        let top = ((detection.y_center - detection.height / 2.0) * height as f32).max(0.0) as i32;
+        let box_width = ((detection.width * width as f32) as u32).max(1);
+        let box_height = ((detection.height * height as f32) as u32).max(1);
+
+        draw_hollow_rect_mut(&mut canvas, Rect::at(left, top).of_size(box_width, box_height), color);
+        if box_width > 2 && box_height > 2 {
+            let inner = Rect::at(left + 1, top + 1).of_size(box_width - 2, box_height - 2);
+            draw_hollow_rect_mut(&mut canvas, inner, color);
+        }
+
+        if let Some(font) = label_font() {
+            let text = format!(
+                "{} {:.2}",
+                class_name(Some(class_names), detection.class_id),
+                detection.confidence
+            );
+            let label_top = (top - 14).max(0);
+            let label_width = (text.len() as u32 * 7).max(1);
+            draw_filled_rect_mut(&mut canvas, Rect::at(left, label_top).of_size(label_width, 14), color);
+            draw_text_mut(&mut canvas, Rgb([0, 0, 0]), left + 1, label_top, Scale::uniform(13.0), font, &text);
+        }
+    }
+
+    DynamicImage::ImageRgb8(canvas)
+}
+
+#[derive(Debug, Clone)]
+pub struct HeatmapAccumulator {
+    width: u32,
+    height: u32,
+    grid: Vec<f32>,
+}
+
+#[allow(dead_code)]
+impl HeatmapAccumulator {
+    pub fn new(resolution: (u32, u32)) -> Self {
+        let (width, height) = (resolution.0.max(1), resolution.1.max(1));
+        Self {
+            width,
+            height,
+            grid: vec![0.0; (width * height) as usize],
+        }
+    }
+
+    pub fn add(&mut self, image_dimensions: (u32, u32), detections: &[Detection]) {
+        if image_dimensions.0 == 0 || image_dimensions.1 == 0 {
+            return;
+        }
+        for detection in detections {
+            let x1 = ((detection.x_center - detection.width / 2.0) * self.width as f32).floor().max(0.0) as u32;
+            let y1 = ((detection.y_center - detection.height / 2.0) * self.height as f32).floor().max(0.0) as u32;
+            let x2 = (((detection.x_center + detection.width / 2.0) * self.width as f32).ceil() as u32).min(self.width);
+            let y2 = (((detection.y_center + detection.height / 2.0) * self.height as f32).ceil() as u32).min(self.height);
+            for y in y1..y2 {
+                for x in x1..x2 {
+                    self.grid[(y * self.width + x) as usize] += 1.0;
+                }
+            }
+        }
+    }
+
+    pub fn render(&self) -> DynamicImage {
+        let max = self.grid.iter().copied().fold(0.0, f32::max);
+        let mut canvas = RgbImage::new(self.width, self.height);
+        for (index, pixel) in canvas.pixels_mut().enumerate() {
+            let value = if max > 0.0 { self.grid[index] / max } else { 0.0 };
+            *pixel = Rgb(jet(value));
+        }
+        DynamicImage::ImageRgb8(canvas)
+    }
+}
+
+// Blue (cold) through green to red (hot)
+fn jet(value: f32) -> [u8; 3] {
+    let channel = |offset: f32| ((1.5 - (4.0 * value - offset).abs()).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(3.0), channel(2.0), channel(1.0)]
+}